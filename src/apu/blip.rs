@@ -0,0 +1,185 @@
+use std::f64::consts::PI;
+
+// Each output sample is split into this many sub-sample positions for placing steps.
+const PHASE_BITS: u32 = 6;
+const PHASES: usize = 1 << PHASE_BITS;
+// Taps in the band-limited step kernel; output is delayed by half of this.
+const KERNEL_WIDTH: usize = 16;
+// The kernel's passband edge as a fraction of the output Nyquist frequency.
+const CUTOFF: f64 = 0.9;
+// Fixed-point scale of the kernel taps; the taps of each phase sum to exactly this,
+// so integrating the buffer never drifts away from the true amplitude.
+const KERNEL_UNIT: i64 = 1 << 15;
+// Fractional bits of sample positions.
+const FRAC_BITS: u32 = 32;
+
+// Band-limited step synthesis: instead of point-sampling the channel output, every
+// change in amplitude is added as a windowed-sinc step at its exact position between
+// output samples, which keeps the harmonics of the square waves from aliasing.
+pub struct BlipBuffer {
+    // output samples per input clock, in 32.32 fixed point
+    factor: u64,
+    // position of input clock 0 of the current frame, in 32.32 fixed point samples
+    offset: u64,
+    // impulses not yet read out; index 0 is the next output sample
+    buffer: Vec<i64>,
+    integrator: i64,
+    kernel: Vec<[i64; KERNEL_WIDTH]>
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> BlipBuffer {
+        BlipBuffer {
+            factor: ((sample_rate as u64) << FRAC_BITS) / clock_rate as u64,
+            offset: 0,
+            buffer: vec![0; KERNEL_WIDTH],
+            integrator: 0,
+            kernel: build_kernel()
+        }
+    }
+
+    // Adds a change in amplitude `clock` input clocks into the current frame.
+    pub fn add_delta(&mut self, clock: u32, delta: i32) {
+        if delta == 0 {
+            return;
+        }
+        let pos = self.offset + clock as u64 * self.factor;
+        let index = (pos >> FRAC_BITS) as usize;
+        let phase = ((pos >> (FRAC_BITS - PHASE_BITS)) as usize) & (PHASES - 1);
+        if self.buffer.len() < index + KERNEL_WIDTH {
+            self.buffer.resize(index + KERNEL_WIDTH, 0);
+        }
+        for (slot, &tap) in self.buffer[index..].iter_mut().zip(self.kernel[phase].iter()) {
+            *slot += delta as i64 * tap;
+        }
+    }
+
+    // Ends the current frame after `clocks` input clocks, making the samples in it readable.
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as u64 * self.factor;
+        let needed = (self.offset >> FRAC_BITS) as usize + KERNEL_WIDTH;
+        if self.buffer.len() < needed {
+            self.buffer.resize(needed, 0);
+        }
+    }
+
+    pub fn samples_available(&self) -> usize {
+        (self.offset >> FRAC_BITS) as usize
+    }
+
+    // Removes up to `count` finished samples, in the same units that were passed to add_delta.
+    pub fn read_samples(&mut self, count: usize, out: &mut Vec<i32>) {
+        let count = count.min(self.samples_available());
+        for impulse in self.buffer.drain(..count) {
+            self.integrator += impulse;
+            out.push((self.integrator / KERNEL_UNIT) as i32);
+        }
+        self.offset -= (count as u64) << FRAC_BITS;
+    }
+}
+
+// One set of taps per phase. Tap i contributes to the output sample KERNEL_WIDTH / 2
+// samples before the one it is added to, which keeps every tap after the step position.
+fn build_kernel() -> Vec<[i64; KERNEL_WIDTH]> {
+    let half = (KERNEL_WIDTH / 2) as f64;
+    (0..PHASES).map(|phase| {
+        let frac = phase as f64 / PHASES as f64;
+        let mut taps = [0f64; KERNEL_WIDTH];
+        for (i, tap) in taps.iter_mut().enumerate() {
+            let d = i as f64 - half - frac;
+            let sinc = if d == 0.0 { 1.0 } else { (PI * CUTOFF * d).sin() / (PI * CUTOFF * d) };
+            // Blackman window over the width of the kernel
+            let w = 0.42 + 0.5 * (PI * d / half).cos() + 0.08 * (2.0 * PI * d / half).cos();
+            *tap = if d.abs() < half { sinc * w } else { 0.0 };
+        }
+        let sum: f64 = taps.iter().sum();
+        let mut fixed = [0i64; KERNEL_WIDTH];
+        for (out, tap) in fixed.iter_mut().zip(taps.iter()) {
+            *out = (tap / sum * KERNEL_UNIT as f64).round() as i64;
+        }
+        // fold the rounding error into the centre tap
+        let error = KERNEL_UNIT - fixed.iter().sum::<i64>();
+        fixed[KERNEL_WIDTH / 2] += error;
+        fixed
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: u32 = 4194304;
+
+    fn read_all(blip: &mut BlipBuffer) -> Vec<i32> {
+        let mut out = Vec::new();
+        let count = blip.samples_available();
+        blip.read_samples(count, &mut out);
+        out
+    }
+
+    #[test]
+    fn kernel_phases_sum_to_unit() {
+        for taps in build_kernel() {
+            assert_eq!(taps.iter().sum::<i64>(), KERNEL_UNIT);
+        }
+    }
+
+    #[test]
+    fn produces_samples_at_output_rate() {
+        let mut blip = BlipBuffer::new(CLOCK_RATE, 48000);
+        let mut total = 0;
+        for _ in 0..64 {
+            blip.end_frame(CLOCK_RATE / 64);
+            total += read_all(&mut blip).len();
+        }
+        assert!((total as i32 - 48000).abs() <= 1, "{} samples", total);
+    }
+
+    #[test]
+    fn step_settles_at_exact_amplitude() {
+        let mut blip = BlipBuffer::new(CLOCK_RATE, 48000);
+        // 100 clocks lands partway into the second output sample.
+        blip.add_delta(100, 10000);
+        blip.end_frame(CLOCK_RATE / 100);
+        let out = read_all(&mut blip);
+        assert_eq!(out[0], 0);
+        assert!(out[2 + KERNEL_WIDTH..].iter().all(|&s| s == 10000));
+    }
+
+    #[test]
+    fn steps_split_across_frames() {
+        let mut blip = BlipBuffer::new(CLOCK_RATE, 48000);
+        let mut out = Vec::new();
+        for frame in 0..10 {
+            let delta = if frame % 2 == 0 { 5000 } else { -5000 };
+            blip.add_delta(7, delta);
+            blip.end_frame(1000);
+            let count = blip.samples_available();
+            blip.read_samples(count, &mut out);
+        }
+        blip.end_frame(CLOCK_RATE / 100);
+        let count = blip.samples_available();
+        blip.read_samples(count, &mut out);
+        assert_eq!(*out.last().unwrap(), 0);
+    }
+
+    #[test]
+    fn attenuates_tones_above_nyquist() {
+        // a 131 kHz square wave would alias down into the audible range if point-sampled.
+        let mut blip = BlipBuffer::new(CLOCK_RATE, 48000);
+        let period = 32;
+        let mut level = 0;
+        for clock in 0..CLOCK_RATE / 10 {
+            if clock % (period / 2) == 0 {
+                let target = if level == 0 { 10000 } else { 0 };
+                blip.add_delta(clock, target - level);
+                level = target;
+            }
+        }
+        blip.end_frame(CLOCK_RATE / 10);
+        let out = read_all(&mut blip);
+        for &s in &out[KERNEL_WIDTH..] {
+            assert!((s - 5000).abs() < 500, "sample {}", s);
+        }
+    }
+}
\ No newline at end of file
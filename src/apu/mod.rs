@@ -0,0 +1,533 @@
+mod square;
+mod wave;
+mod noise;
+mod blip;
+use self::square::Square;
+use self::wave::Wave;
+use self::noise::Noise;
+use self::blip::BlipBuffer;
+
+// T-cycles per second; the channels and frame sequencer run off this clock.
+pub const CLOCK_RATE: u32 = 4194304;
+// T-cycles between frame sequencer steps (512 Hz).
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+// Mixer output in -1.0..1.0 is resampled as integers in this range.
+const AMPLITUDE_SCALE: f32 = 32767.0;
+
+// Bits of each sound register that always read back as 1 (write-only or unused),
+// indexed from NR10 (0xFF10) to 0xFF2F.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // (unused), NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // (unused), NR41-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF
+];
+
+// Turns a channel off after a set number of 256 Hz clocks.
+pub struct Length {
+    pub counter: u16,
+    pub enabled: bool,
+    max: u16
+}
+
+impl Length {
+    pub fn new(max: u16) -> Length {
+        Length {
+            counter: 0,
+            enabled: false,
+            max
+        }
+    }
+
+    // NRx1 holds how far into the count the channel starts.
+    pub fn load(&mut self, len: u16) {
+        self.counter = self.max - len;
+    }
+
+    // Returns true when the counter runs out and the channel should be disabled.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Handles the length half of an NRx4 write. `frame_step` is the next frame sequencer
+    // step: when that step won't clock length, enabling it clocks it once straight away,
+    // which is also why a trigger in that window reloads one short of the maximum.
+    // Returns true if the write expired the counter.
+    pub fn write_control(&mut self, val: u8, frame_step: u8) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = val & 0x40 != 0;
+        let extra_clock = frame_step & 1 == 1;
+        let mut expired = false;
+        if extra_clock && !was_enabled && self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0;
+        }
+        if val & 0x80 != 0 {
+            if self.counter == 0 {
+                self.counter = self.max;
+                if extra_clock && self.enabled {
+                    self.counter -= 1;
+                }
+            }
+            return false;
+        }
+        expired
+    }
+}
+
+// Volume envelope shared by the square and noise channels (NRx2).
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    pub volume: u8
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            initial: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0
+        }
+    }
+
+    pub fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    // Clocked at 64 Hz.
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// The output capacitor that blocks DC from the DACs. Its charge factors are per
+// T-cycle; the CGB's discharges noticeably faster.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HighPass {
+    Off,
+    Dmg,
+    Cgb
+}
+
+impl HighPass {
+    pub fn from_name(name: &str) -> Option<HighPass> {
+        match name {
+            "off" => Some(HighPass::Off),
+            "dmg" => Some(HighPass::Dmg),
+            "cgb" => Some(HighPass::Cgb),
+            _ => None
+        }
+    }
+
+    // How much of the capacitor's charge is left after one output sample.
+    fn charge(self, sample_rate: u32) -> f32 {
+        let per_cycle: f64 = match self {
+            HighPass::Off => 1.0,
+            HighPass::Dmg => 0.999958,
+            HighPass::Cgb => 0.998943
+        };
+        per_cycle.powf(CLOCK_RATE as f64 / sample_rate as f64) as f32
+    }
+}
+
+pub struct APU {
+    // last values written to NR10-NR51, for reading back
+    registers: [u8; 0x20],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    powered: bool,
+    nr50: u8,
+    nr51: u8,
+    // the next frame sequencer step (0-7) and T-cycles until it happens
+    frame_step: u8,
+    frame_timer: u32,
+    sample_rate: u32,
+    left: BlipBuffer,
+    right: BlipBuffer,
+    // T-cycles into the current resampler frame, and the amplitudes last fed to it
+    frame_clock: u32,
+    amplitude: (i32, i32),
+    high_pass: HighPass,
+    charge: f32,
+    capacitor: (f32, f32),
+    // interleaved left/right output, drained by whatever plays it
+    pub samples: Vec<f32>
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            registers: [0; 0x20],
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            powered: false,
+            nr50: 0,
+            nr51: 0,
+            frame_step: 0,
+            frame_timer: FRAME_SEQUENCER_PERIOD,
+            sample_rate: 48000,
+            left: BlipBuffer::new(CLOCK_RATE, 48000),
+            right: BlipBuffer::new(CLOCK_RATE, 48000),
+            frame_clock: 0,
+            amplitude: (0, 0),
+            high_pass: HighPass::Dmg,
+            charge: HighPass::Dmg.charge(48000),
+            capacitor: (0.0, 0.0),
+            samples: Vec::new()
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, CLOCK_RATE);
+        self.left = BlipBuffer::new(CLOCK_RATE, self.sample_rate);
+        self.right = BlipBuffer::new(CLOCK_RATE, self.sample_rate);
+        self.frame_clock = 0;
+        self.amplitude = (0, 0);
+        self.charge = self.high_pass.charge(self.sample_rate);
+    }
+
+    pub fn high_pass(&self) -> HighPass {
+        self.high_pass
+    }
+
+    pub fn set_high_pass(&mut self, filter: HighPass) {
+        self.high_pass = filter;
+        self.charge = filter.charge(self.sample_rate);
+        self.capacitor = (0.0, 0.0);
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => {
+                let status = (self.square1.enabled as u8)
+                    | (self.square2.enabled as u8) << 1
+                    | (self.wave.enabled as u8) << 2
+                    | (self.noise.enabled as u8) << 3;
+                (self.powered as u8) << 7 | READ_MASKS[0x16] | status
+            },
+            0xFF10..=0xFF2F => {
+                let i = (addr - 0xFF10) as usize;
+                self.registers[i] | READ_MASKS[i]
+            },
+            _ => self.wave.read_ram((addr - 0xFF30) as usize)
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF26 => self.set_power(val & 0x80 != 0),
+            0xFF30..=0xFF3F => self.wave.write_ram((addr - 0xFF30) as usize, val),
+            // everything but NR52 and wave RAM is read-only while powered off
+            _ if !self.powered => {},
+            0xFF10..=0xFF2F => {
+                self.registers[(addr - 0xFF10) as usize] = val;
+                let step = self.frame_step;
+                match addr {
+                    0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, val, step),
+                    0xFF16..=0xFF19 => self.square2.write(addr - 0xFF15, val, step),
+                    0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, val, step),
+                    0xFF20..=0xFF23 => self.noise.write(addr - 0xFF1F, val, step),
+                    0xFF24 => self.nr50 = val,
+                    0xFF25 => self.nr51 = val,
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_step = 0;
+            self.frame_timer = FRAME_SEQUENCER_PERIOD;
+        } else if !on && self.powered {
+            // powering off clears every register; wave RAM survives.
+            let wave_ram = self.wave.ram;
+            self.registers = [0; 0x20];
+            self.square1 = Square::new(true);
+            self.square2 = Square::new(false);
+            self.wave = Wave::new();
+            self.wave.ram = wave_ram;
+            self.noise = Noise::new();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        }
+        self.powered = on;
+    }
+
+    // Advances the APU by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        // picks up anything register writes changed since the last tick
+        self.update_amplitude();
+        let mut remaining = cycles;
+        while remaining > 0 {
+            // run up to the next point where the output can change
+            let mut n = remaining.min(self.frame_timer);
+            if self.powered {
+                let changes = [self.square1.next_change(), self.square2.next_change(),
+                               self.wave.next_change(), self.noise.next_change()];
+                for &next in changes.iter().flatten() {
+                    n = n.min(next);
+                }
+                self.square1.tick(n);
+                self.square2.tick(n);
+                self.wave.tick(n);
+                self.noise.tick(n);
+            }
+            self.frame_clock += n;
+            self.frame_timer -= n;
+            if self.frame_timer == 0 {
+                self.frame_timer = FRAME_SEQUENCER_PERIOD;
+                if self.powered {
+                    self.clock_frame_sequencer();
+                }
+            }
+            self.update_amplitude();
+            remaining -= n;
+        }
+        self.left.end_frame(self.frame_clock);
+        self.right.end_frame(self.frame_clock);
+        self.frame_clock = 0;
+        self.read_output();
+    }
+
+    fn update_amplitude(&mut self) {
+        let (left, right) = self.mix();
+        let left = (left * AMPLITUDE_SCALE) as i32;
+        let right = (right * AMPLITUDE_SCALE) as i32;
+        self.left.add_delta(self.frame_clock, left - self.amplitude.0);
+        self.right.add_delta(self.frame_clock, right - self.amplitude.1);
+        self.amplitude = (left, right);
+    }
+
+    // Moves finished samples out of the resamplers, through the high-pass filter and into `samples`.
+    fn read_output(&mut self) {
+        let count = self.left.samples_available();
+        if count == 0 {
+            return;
+        }
+        let mut left = Vec::with_capacity(count);
+        let mut right = Vec::with_capacity(count);
+        self.left.read_samples(count, &mut left);
+        self.right.read_samples(count, &mut right);
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            let l = l as f32 / AMPLITUDE_SCALE;
+            let r = r as f32 / AMPLITUDE_SCALE;
+            let out_l = l - self.capacitor.0;
+            let out_r = r - self.capacitor.1;
+            self.capacitor.0 = l - out_l * self.charge;
+            self.capacitor.1 = r - out_r * self.charge;
+            self.samples.push(out_l);
+            self.samples.push(out_r);
+        }
+    }
+
+    // Length on even steps, sweep on 2 and 6, envelopes on 7.
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_step;
+        if step & 1 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.square1.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (step + 1) & 7;
+    }
+
+    // Returns the current left and right output in -1.0..1.0.
+    fn mix(&self) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+        let outputs = [self.square1.output(), self.square2.output(), self.wave.output(), self.noise.output()];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, out) in outputs.iter().enumerate() {
+            // each DAC maps 0-15 linearly onto -1.0..1.0; a disabled DAC outputs nothing.
+            let analog = match *out {
+                Some(digital) => digital as f32 / 7.5 - 1.0,
+                None => 0.0
+            };
+            if self.nr51 & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if self.nr51 & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+        // NR50 volumes are 1-8 eighths; dividing by 4 more keeps four full-scale channels in range.
+        let left_volume = (((self.nr50 >> 4) & 0x07) + 1) as f32;
+        let right_volume = ((self.nr50 & 0x07) + 1) as f32;
+        (left * left_volume / 32.0, right * right_volume / 32.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered() -> APU {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF26, 0x80);
+        apu
+    }
+
+    // Channel 1 at full volume, with NRx1 length `len` and NRx4 `control` (trigger included).
+    fn trigger_square1(apu: &mut APU, len: u8, control: u8) {
+        apu.write_byte(0xFF11, len);
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF14, control);
+    }
+
+    #[test]
+    fn registers_read_back_with_masks() {
+        let mut apu = powered();
+        apu.write_byte(0xFF11, 0x81);
+        assert_eq!(apu.read_byte(0xFF11), 0xBF);
+        apu.write_byte(0xFF13, 0x12);
+        assert_eq!(apu.read_byte(0xFF13), 0xFF);
+        apu.write_byte(0xFF1A, 0x00);
+        assert_eq!(apu.read_byte(0xFF1A), 0x7F);
+        assert_eq!(apu.read_byte(0xFF15), 0xFF);
+        assert_eq!(apu.read_byte(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn nr52_reports_channel_status() {
+        let mut apu = powered();
+        trigger_square1(&mut apu, 0, 0x80);
+        assert_eq!(apu.read_byte(0xFF26), 0xF1);
+        // turning the DAC off disables the channel.
+        apu.write_byte(0xFF12, 0x00);
+        assert_eq!(apu.read_byte(0xFF26), 0xF0);
+        // and a trigger with it off doesn't start it.
+        apu.write_byte(0xFF14, 0x80);
+        assert_eq!(apu.read_byte(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn power_off_clears_registers_but_not_wave_ram() {
+        let mut apu = powered();
+        apu.write_byte(0xFF30, 0x5A);
+        apu.write_byte(0xFF24, 0x77);
+        trigger_square1(&mut apu, 0, 0x80);
+        apu.write_byte(0xFF26, 0x00);
+        assert_eq!(apu.read_byte(0xFF26), 0x70);
+        assert_eq!(apu.read_byte(0xFF24), 0x00);
+        assert_eq!(apu.read_byte(0xFF30), 0x5A);
+        // registers ignore writes until power comes back.
+        apu.write_byte(0xFF24, 0x77);
+        assert_eq!(apu.read_byte(0xFF24), 0x00);
+        apu.write_byte(0xFF26, 0x80);
+        apu.write_byte(0xFF24, 0x77);
+        assert_eq!(apu.read_byte(0xFF24), 0x77);
+    }
+
+    #[test]
+    fn length_counter_disables_channel() {
+        let mut apu = powered();
+        trigger_square1(&mut apu, 0x3E, 0xC0);
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x01);
+        apu.tick(FRAME_SEQUENCER_PERIOD * 2 - 1);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x01);
+        apu.tick(1);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn length_only_counts_when_enabled() {
+        let mut apu = powered();
+        trigger_square1(&mut apu, 0x3F, 0x80);
+        apu.tick(FRAME_SEQUENCER_PERIOD * 16);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn enabling_length_on_odd_step_clocks_it() {
+        let mut apu = powered();
+        trigger_square1(&mut apu, 0x3F, 0x80);
+        // after one step the next one (1) doesn't clock length.
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        apu.write_byte(0xFF14, 0x40);
+        assert_eq!(apu.read_byte(0xFF26) & 0x01, 0x00);
+    }
+
+    // Channel 1's DAC on with nothing playing holds both outputs at a constant -0.25.
+    fn dc_output(filter: HighPass) -> Vec<f32> {
+        let mut apu = powered();
+        apu.set_high_pass(filter);
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF25, 0x11);
+        apu.write_byte(0xFF12, 0x08);
+        apu.tick(CLOCK_RATE / 4);
+        apu.samples
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        let samples = dc_output(HighPass::Dmg);
+        assert!(samples[64] < -0.2);
+        assert!(samples[samples.len() - 1].abs() < 0.01);
+        let samples = dc_output(HighPass::Cgb);
+        assert!(samples[samples.len() - 1].abs() < 0.001);
+    }
+
+    #[test]
+    fn high_pass_off_keeps_dc() {
+        let samples = dc_output(HighPass::Off);
+        assert!((samples[samples.len() - 1] + 0.25).abs() < 0.001);
+    }
+
+    #[test]
+    fn output_matches_sample_rate() {
+        let mut apu = powered();
+        apu.set_sample_rate(48000);
+        apu.tick(CLOCK_RATE);
+        let frames = apu.samples.len() as i32 / 2;
+        assert!((frames - 48000).abs() <= 2, "{} frames", frames);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use apu::{Envelope, Length};
+
+// Base periods in T-cycles for the NR43 divisor codes.
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Channel 4 outputs the low bit of a linear-feedback shift register.
+pub struct Noise {
+    pub enabled: bool,
+    dac: bool,
+    shift: u8,
+    // 7-bit mode, which gives a short, more tonal sequence
+    narrow: bool,
+    divisor: u8,
+    timer: u32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            dac: false,
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: Length::new(64),
+            envelope: Envelope::new()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    // `reg` is 1-4 for NR41-NR44.
+    pub fn write(&mut self, reg: u16, val: u8, frame_step: u8) {
+        match reg {
+            1 => self.length.load((val & 0x3F) as u16),
+            2 => {
+                self.envelope.write(val);
+                self.dac = val & 0xF8 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            },
+            3 => {
+                self.shift = val >> 4;
+                self.narrow = val & 0x08 != 0;
+                self.divisor = val & 0x07;
+            },
+            4 => {
+                if self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    // T-cycles until the output can next change, or None while the channel is silent.
+    pub fn next_change(&self) -> Option<u32> {
+        if self.enabled { Some(self.timer.max(1)) } else { None }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // The digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        Some((!self.lfsr & 1) as u8 * self.envelope.volume)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,174 @@
+use apu::{Envelope, Length};
+
+// Waveforms for the four duty settings, played from the high bit down.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// Square channels 1 and 2. Only channel 1 has the frequency sweep.
+pub struct Square {
+    pub enabled: bool,
+    dac: bool,
+    duty: u8,
+    duty_pos: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    has_sweep: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+    // clearing the negate bit after a subtraction has been calculated disables the channel
+    sweep_negated: bool
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Square {
+        Square {
+            enabled: false,
+            dac: false,
+            duty: 0,
+            duty_pos: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            has_sweep,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+            sweep_negated: false
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    // `reg` is 0-4 for NRx0-NRx4.
+    pub fn write(&mut self, reg: u16, val: u8, frame_step: u8) {
+        match reg {
+            0 if self.has_sweep => {
+                self.sweep_period = (val >> 4) & 0x07;
+                self.sweep_negate = val & 0x08 != 0;
+                self.sweep_shift = val & 0x07;
+                if !self.sweep_negate && self.sweep_negated {
+                    self.enabled = false;
+                }
+            },
+            1 => {
+                self.duty = val >> 6;
+                self.length.load((val & 0x3F) as u16);
+            },
+            2 => {
+                self.envelope.write(val);
+                self.dac = val & 0xF8 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+                if self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.timer = self.period();
+        self.envelope.trigger();
+        if self.has_sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            self.sweep_negated = false;
+            if self.sweep_shift != 0 {
+                self.sweep_calculate();
+            }
+        }
+    }
+
+    // Works out the next swept frequency, disabling the channel if it overflows.
+    fn sweep_calculate(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let next = if self.sweep_negate {
+            self.sweep_negated = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+        if next > 2047 {
+            self.enabled = false;
+        }
+        next
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) & 7;
+        }
+        self.timer -= cycles;
+    }
+
+    // T-cycles until the output can next change, or None while the channel is silent.
+    pub fn next_change(&self) -> Option<u32> {
+        if self.enabled { Some(self.timer.max(1)) } else { None }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer != 0 {
+            return;
+        }
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let next = self.sweep_calculate();
+            if next <= 2047 && self.sweep_shift != 0 {
+                self.shadow_frequency = next;
+                self.frequency = next;
+                // the new frequency is checked for overflow again straight away
+                self.sweep_calculate();
+            }
+        }
+    }
+
+    // The digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_pos)) & 1;
+        Some(high * self.envelope.volume)
+    }
+}
\ No newline at end of file
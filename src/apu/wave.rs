@@ -0,0 +1,116 @@
+use apu::Length;
+
+// Right shifts applied to each 4-bit sample for the NR32 output levels (mute, 100%, 50%, 25%).
+const VOLUME_SHIFTS: [u8; 4] = [4, 0, 1, 2];
+
+// Channel 3 plays the 32 4-bit samples in wave RAM.
+pub struct Wave {
+    pub enabled: bool,
+    dac: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    // the last sample read from wave RAM, which is what the channel outputs
+    sample: u8,
+    length: Length,
+    pub ram: [u8; 0x10]
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: Length::new(256),
+            ram: [0; 0x10]
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    // While the channel is playing, wave RAM accesses land on the byte it is currently reading.
+    pub fn read_ram(&self, index: usize) -> u8 {
+        if self.enabled {
+            self.ram[(self.position / 2) as usize]
+        } else {
+            self.ram[index]
+        }
+    }
+
+    pub fn write_ram(&mut self, index: usize, val: u8) {
+        if self.enabled {
+            self.ram[(self.position / 2) as usize] = val;
+        } else {
+            self.ram[index] = val;
+        }
+    }
+
+    // `reg` is 0-4 for NR30-NR34.
+    pub fn write(&mut self, reg: u16, val: u8, frame_step: u8) {
+        match reg {
+            0 => {
+                self.dac = val & 0x80 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(val as u16),
+            2 => self.volume_code = (val >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+                if self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[(self.position / 2) as usize];
+            self.sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+        self.timer -= cycles;
+    }
+
+    // T-cycles until the output can next change, or None while the channel is silent.
+    pub fn next_change(&self) -> Option<u32> {
+        if self.enabled { Some(self.timer.max(1)) } else { None }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    // The digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        Some(self.sample >> VOLUME_SHIFTS[self.volume_code as usize])
+    }
+}
\ No newline at end of file
@@ -0,0 +1,111 @@
+extern crate gb_em;
+use gb_em::cpu::CPU;
+use gb_em::cartridge::Cartridge;
+use gb_em::mbc::RtcClock;
+use gb_em::mmu::Model;
+use gb_em::ppu::FifoRenderer;
+use gb_em::headless::{self, Condition, Outcome};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::io;
+use std::process;
+
+// Exit statuses: the run stopped on a --until condition (or ran all its frames if there
+// were none), it stopped on --fail-serial, the command line was wrong, or it ran out
+// of frames waiting.
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+
+fn main() {
+    let mut path = None;
+    let mut fifo = false;
+    let mut frames = 600;
+    let mut png = None;
+    let mut model = None;
+    let mut boot_rom = None;
+    let mut conditions = Vec::new();
+    // --fail-serial patterns, which go after the success conditions
+    let mut failures = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fifo" => fifo = true,
+            "--frames" => frames = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()),
+            "--png" => png = Some(args.next().unwrap_or_else(|| usage())),
+            "--model" => model = Some(args.next().and_then(|s| Model::from_name(&s)).unwrap_or_else(|| usage())),
+            "--boot-rom" => boot_rom = Some(args.next().unwrap_or_else(|| usage())),
+            "--until-pc" => conditions.push(Condition::Pc(parse_address(args.next()))),
+            "--until-serial" => conditions.push(Condition::Serial(args.next().unwrap_or_else(|| usage()).into_bytes())),
+            "--until-change" => conditions.push(Condition::MemoryChange(parse_address(args.next()))),
+            "--fail-serial" => failures.push(Condition::Serial(args.next().unwrap_or_else(|| usage()).into_bytes())),
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown option {}", arg);
+                usage();
+            },
+            _ if path.is_some() => {
+                eprintln!("unexpected argument {}", arg);
+                usage();
+            },
+            _ => path = Some(arg)
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    // the emulated RTC keeps runs reproducible
+    let cartridge = match Cartridge::from_file_with_clock(&path, RtcClock::Emulated) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(EXIT_FAILED);
+        }
+    };
+    let model = model.unwrap_or_else(|| Model::for_cartridge(&cartridge));
+    let mut cpu = CPU::new();
+    if let Some(boot_rom) = boot_rom {
+        cpu.mmu.boot_rom = match fs::read(&boot_rom) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                eprintln!("{}: {}", boot_rom, e);
+                process::exit(EXIT_FAILED);
+            }
+        };
+    }
+    cpu.load_cartridge_with_model(cartridge, model);
+    if fifo {
+        cpu.mmu.ppu.set_renderer(Box::new(FifoRenderer::new()));
+    }
+    let successes = conditions.len();
+    let any_conditions = successes > 0;
+    conditions.extend(failures);
+    let status = match headless::run(&mut cpu, frames, &conditions) {
+        Outcome::Met(i) if i < successes => EXIT_OK,
+        Outcome::Met(_) => EXIT_FAILED,
+        Outcome::FrameLimit if any_conditions => EXIT_TIMEOUT,
+        Outcome::FrameLimit => EXIT_OK
+    };
+    let _ = io::stdout().write_all(&cpu.mmu.serial.output);
+    if let Some(png) = png {
+        if let Err(e) = headless::write_png(&cpu, &png) {
+            eprintln!("{}: {}", png, e);
+            process::exit(EXIT_FAILED);
+        }
+    }
+    // process::exit skips destructors, so flush the save file first
+    drop(cpu);
+    process::exit(status);
+}
+
+fn parse_address(arg: Option<String>) -> u16 {
+    let arg = arg.unwrap_or_else(|| usage());
+    let digits = arg.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("usage: gb_em_headless [--frames N] [--until-pc ADDR] [--until-serial TEXT] \
+               [--until-change ADDR] [--fail-serial TEXT] [--png FILE] [--fifo] \
+               [--model dmg|mgb|cgb] [--boot-rom FILE] <rom>");
+    process::exit(EXIT_USAGE);
+}
\ No newline at end of file
@@ -0,0 +1,20 @@
+// What the CPU sees of the rest of the machine. The MMU is the real thing;
+// tests can put something simpler in its place.
+pub trait Bus {
+    fn read_byte(&mut self, addr: u16) -> u8;
+    fn write_byte(&mut self, addr: u16, val: u8);
+    // Advances everything outside the CPU by `cycles` M-cycles.
+    fn tick(&mut self, cycles: u32);
+    // IE & IF: the interrupts that are both requested and enabled.
+    fn pending_interrupts(&self) -> u8;
+    fn acknowledge_interrupt(&mut self, bit: u8);
+    // Takes one M-cycle off a stall (speed switch, VRAM DMA); false if there's none.
+    fn stall(&mut self) -> bool;
+    // Tells the hardware when the CPU enters or leaves HALT.
+    fn set_halted(&mut self, halted: bool);
+    // Whether a selected joypad line is low, which wakes the CPU from STOP.
+    fn joypad_active(&self) -> bool;
+    // Executes STOP: switches speed if a switch is armed and returns true; either
+    // way DIV is reset.
+    fn stop(&mut self) -> bool;
+}
\ No newline at end of file
@@ -0,0 +1,422 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use mbc::{MBC, RomOnly, MBC1, MBC2, MBC3, MBC5, RtcClock};
+
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
+];
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < 0x150 {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+        let computed = header_checksum(rom);
+        if computed != rom[0x14D] {
+            return Err(CartridgeError::HeaderChecksum { expected: rom[0x14D], computed });
+        }
+        let cgb_flag = rom[0x143];
+        // CGB titles reuse the last title byte as the CGB flag.
+        let title_end = if cgb_flag & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title = rom[0x134..title_end].iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+        Ok(CartridgeHeader {
+            title,
+            cgb_flag,
+            cartridge_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+            header_checksum: rom[0x14D],
+            global_checksum: ((rom[0x14E] as u16) << 8) | (rom[0x14F] as u16)
+        })
+    }
+
+    pub fn rom_bytes(&self) -> Result<usize, CartridgeError> {
+        match self.rom_size {
+            0x00..=0x08 => Ok(0x8000 << self.rom_size),
+            _ => Err(CartridgeError::InvalidRomSize(self.rom_size))
+        }
+    }
+
+    pub fn ram_bytes(&self) -> Result<usize, CartridgeError> {
+        match self.ram_size {
+            0x00 => Ok(0),
+            0x01 => Ok(0x800),
+            0x02 => Ok(0x2000),
+            0x03 => Ok(0x8000),
+            0x04 => Ok(0x20000),
+            0x05 => Ok(0x10000),
+            _ => Err(CartridgeError::InvalidRamSize(self.ram_size))
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+}
+
+// The boot ROM refuses to start a cartridge whose header checksum doesn't match;
+// the global checksum is never verified by hardware.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    TooSmall(usize),
+    HeaderChecksum { expected: u8, computed: u8 },
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    UnsupportedType(u8)
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Io(ref e) => write!(f, "could not read ROM: {}", e),
+            CartridgeError::TooSmall(len) => write!(f, "ROM is too small to contain a header ({} bytes)", len),
+            CartridgeError::HeaderChecksum { expected, computed } =>
+                write!(f, "bad header checksum (expected {:02x}, computed {:02x})", expected, computed),
+            CartridgeError::InvalidRomSize(code) => write!(f, "invalid ROM size code {:02x}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "invalid RAM size code {:02x}", code),
+            CartridgeError::UnsupportedType(t) => write!(f, "unsupported cartridge type {:02x}", t)
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> CartridgeError {
+        CartridgeError::Io(e)
+    }
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    mbc: Box<dyn MBC>,
+    // where battery-backed RAM is persisted; only set for battery carts loaded from a file.
+    save_path: Option<PathBuf>
+}
+
+impl Cartridge {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_file_with_clock(path, RtcClock::WallClock)
+    }
+
+    pub fn from_file_with_clock<P: AsRef<Path>>(path: P, clock: RtcClock) -> Result<Cartridge, CartridgeError> {
+        let mut data = Vec::new();
+        File::open(path.as_ref())?.read_to_end(&mut data)?;
+        let mut cartridge = Cartridge::from_bytes_with_clock(data, clock)?;
+        if cartridge.header.has_battery() {
+            let save_path = path.as_ref().with_extension("sav");
+            cartridge.load_save(&save_path)?;
+            cartridge.save_path = Some(save_path);
+        }
+        Ok(cartridge)
+    }
+
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes_with_clock(rom, RtcClock::WallClock)
+    }
+
+    // `clock` selects what drives the real-time clock on MBC3 cartridges that have one.
+    pub fn from_bytes_with_clock(mut rom: Vec<u8>, clock: RtcClock) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let rom_bytes = header.rom_bytes()?;
+        let ram_bytes = header.ram_bytes()?;
+        // truncated dumps are padded out to the size the header declares, so bank
+        // numbers can always be masked against a power-of-two ROM size.
+        let padded = rom_bytes.max(rom.len().next_power_of_two());
+        rom.resize(padded, 0xFF);
+        let mbc: Box<dyn MBC> = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Box::new(RomOnly::new(rom, ram_bytes)),
+            0x01..=0x03 => {
+                let multicart = is_mbc1_multicart(&rom);
+                Box::new(MBC1::new(rom, ram_bytes, multicart))
+            },
+            0x05 | 0x06 => Box::new(MBC2::new(rom)),
+            0x0F | 0x10 => Box::new(MBC3::new(rom, ram_bytes, true, clock)),
+            0x11..=0x13 => Box::new(MBC3::new(rom, ram_bytes, false, clock)),
+            0x19..=0x1B => Box::new(MBC5::new(rom, ram_bytes, false)),
+            0x1C..=0x1E => Box::new(MBC5::new(rom, ram_bytes, true)),
+            t => return Err(CartridgeError::UnsupportedType(t))
+        };
+        Ok(Cartridge {
+            header,
+            mbc,
+            save_path: None
+        })
+    }
+
+    fn load_save(&mut self, path: &Path) -> io::Result<()> {
+        let mut data = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e)
+        };
+        let ram_len = self.mbc.ram().len().min(data.len());
+        self.mbc.ram_mut()[..ram_len].copy_from_slice(&data[..ram_len]);
+        self.mbc.load_rtc_footer(&data[ram_len..]);
+        Ok(())
+    }
+
+    // Writes battery-backed RAM (and the RTC, if any) to the .sav file next to the ROM.
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = match self.save_path {
+            Some(ref path) => path.clone(),
+            None => return Ok(())
+        };
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(footer) = self.mbc.rtc_footer() {
+            data.extend_from_slice(&footer);
+        }
+        File::create(path)?.write_all(&data)
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.read_rom(addr),
+            0xA000..=0xBFFF => self.mbc.read_ram(addr),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.write_rom(addr, val),
+            0xA000..=0xBFFF => self.mbc.write_ram(addr, val),
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            eprintln!("could not write save file: {}", e);
+        }
+    }
+}
+
+// MBC1M multicarts are 8 Mbit MBC1 boards holding several 2 Mbit games, each
+// with its own header. There's no header flag for them, so look for a second
+// copy of the Nintendo logo at the start of the game in bank 0x10.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO[..]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom[0x14D] = header_checksum(&rom);
+        rom
+    }
+
+    // A ROM file in its own scratch directory, so its .sav lands next to it.
+    fn rom_file(name: &str, rom: &[u8]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gb_em_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gb");
+        fs::write(&path, rom).unwrap();
+        let _ = fs::remove_file(path.with_extension("sav"));
+        path
+    }
+
+    fn read_rtc(cartridge: &mut Cartridge) -> Vec<u8> {
+        cartridge.write_byte(0x6000, 0x00);
+        cartridge.write_byte(0x6000, 0x01);
+        (0x08..0x0D).map(|reg| {
+            cartridge.write_byte(0x4000, reg);
+            cartridge.read_byte(0xA000)
+        }).collect()
+    }
+
+    // Re-signs a ROM after its header has been edited.
+    fn fix_checksum(mut rom: Vec<u8>) -> Vec<u8> {
+        rom[0x14D] = header_checksum(&rom);
+        rom
+    }
+
+    fn with_title(title: &[u8], cgb_flag: u8) -> CartridgeHeader {
+        let mut rom = rom(0x00, 0x00);
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x143] = cgb_flag;
+        CartridgeHeader::parse(&fix_checksum(rom)).unwrap()
+    }
+
+    #[test]
+    fn rejects_rom_without_header() {
+        let result = Cartridge::from_bytes(vec![0; 0x14F]);
+        assert!(matches!(result, Err(CartridgeError::TooSmall(0x14F))));
+    }
+
+    #[test]
+    fn rejects_bad_header_checksum() {
+        let mut rom = rom(0x00, 0x00);
+        let good = rom[0x14D];
+        rom[0x14D] = good ^ 0x01;
+        match Cartridge::from_bytes(rom) {
+            Err(CartridgeError::HeaderChecksum { expected, computed }) => {
+                assert_eq!((expected, computed), (good ^ 0x01, good));
+            },
+            _ => panic!("bad checksum accepted")
+        }
+    }
+
+    #[test]
+    fn rejects_bad_size_codes_and_types() {
+        let mut bad_rom_size = rom(0x00, 0x00);
+        bad_rom_size[0x148] = 0x09;
+        assert!(matches!(Cartridge::from_bytes(fix_checksum(bad_rom_size)), Err(CartridgeError::InvalidRomSize(0x09))));
+        let bad_ram_size = rom(0x00, 0x06);
+        assert!(matches!(Cartridge::from_bytes(bad_ram_size), Err(CartridgeError::InvalidRamSize(0x06))));
+        // 0xFC is the Pocket Camera.
+        let unsupported = rom(0xFC, 0x00);
+        assert!(matches!(Cartridge::from_bytes(unsupported), Err(CartridgeError::UnsupportedType(0xFC))));
+    }
+
+    #[test]
+    fn parses_header() {
+        let mut rom = rom(0x13, 0x03);
+        rom[0x148] = 0x05;
+        rom[0x14E] = 0x12;
+        rom[0x14F] = 0x34;
+        let header = CartridgeHeader::parse(&fix_checksum(rom)).unwrap();
+        assert_eq!(header.cartridge_type, 0x13);
+        assert_eq!(header.rom_bytes().unwrap(), 0x100000);
+        assert_eq!(header.ram_bytes().unwrap(), 0x8000);
+        assert_eq!(header.global_checksum, 0x1234);
+        assert!(header.has_battery());
+    }
+
+    #[test]
+    fn title_and_cgb_flag() {
+        let header = with_title(b"TETRIS", 0x00);
+        assert_eq!((header.title.as_str(), header.cgb_flag), ("TETRIS", 0x00));
+        // a DMG title can use all 16 bytes, flag byte included...
+        assert_eq!(with_title(b"SIXTEEN CHARS A", b'B').title, "SIXTEEN CHARS AB");
+        // ...while on a CGB title the last one is the flag.
+        let header = with_title(b"FIFTEEN CHARS AB", 0x80);
+        assert_eq!((header.title.as_str(), header.cgb_flag), ("FIFTEEN CHARS A", 0x80));
+        assert_eq!(with_title(b"CGB ONLY", 0xC0).title, "CGB ONLY");
+    }
+
+    #[test]
+    fn rom_only_mapping() {
+        let mut rom = rom(0x00, 0x00);
+        rom[0x0000] = 0x11;
+        rom[0x4000] = 0x22;
+        rom[0x7FFF] = 0x33;
+        let mut cartridge = Cartridge::from_bytes(fix_checksum(rom)).unwrap();
+        // there's no mapper to switch banks, and no RAM.
+        cartridge.write_byte(0x2000, 0x02);
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA000, 0x44);
+        assert_eq!(cartridge.read_byte(0x0000), 0x11);
+        assert_eq!(cartridge.read_byte(0x4000), 0x22);
+        assert_eq!(cartridge.read_byte(0x7FFF), 0x33);
+        assert_eq!(cartridge.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn save_round_trip_with_rtc_footer() {
+        let path = rom_file("rtc", &rom(0x10, 0x03));
+        {
+            let mut cartridge = Cartridge::from_file_with_clock(&path, RtcClock::Emulated).unwrap();
+            cartridge.write_byte(0x0000, 0x0A);
+            cartridge.write_byte(0x4000, 0x03);
+            cartridge.write_byte(0xBFFF, 0x5A);
+            for (reg, val) in [(0x08, 12), (0x09, 34), (0x0A, 5), (0x0B, 0x42), (0x0C, 0x41)] {
+                cartridge.write_byte(0x4000, reg);
+                cartridge.write_byte(0xA000, val);
+            }
+        }
+        let data = fs::read(path.with_extension("sav")).unwrap();
+        assert_eq!(data.len(), 0x8000 + 48);
+        assert_eq!(data[0x7FFF], 0x5A);
+        // seconds, minutes, hours, days low and days high, as little-endian u32s.
+        assert_eq!(data[0x8000..0x8014], [12, 0, 0, 0, 34, 0, 0, 0, 5, 0, 0, 0, 0x42, 0, 0, 0, 0x41, 0, 0, 0]);
+        let mut cartridge = Cartridge::from_file_with_clock(&path, RtcClock::Emulated).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x4000, 0x03);
+        assert_eq!(cartridge.read_byte(0xBFFF), 0x5A);
+        assert_eq!(read_rtc(&mut cartridge), [12, 34, 5, 0x42, 0x41]);
+        drop(cartridge);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn save_with_short_rtc_footer() {
+        let path = rom_file("rtc44", &rom(0x10, 0x03));
+        let mut data = vec![0; 0x8000 + 44];
+        data[0x8000] = 59;
+        data[0x8010] = 0x40;
+        fs::write(path.with_extension("sav"), &data).unwrap();
+        let mut cartridge = Cartridge::from_file_with_clock(&path, RtcClock::Emulated).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(read_rtc(&mut cartridge), [59, 0, 0, 0, 0x40]);
+        drop(cartridge);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn no_save_without_battery() {
+        let path = rom_file("nobattery", &rom(0x01, 0x02));
+        {
+            let mut cartridge = Cartridge::from_file(&path).unwrap();
+            cartridge.write_byte(0x0000, 0x0A);
+            cartridge.write_byte(0xA000, 0x12);
+        }
+        assert!(!path.with_extension("sav").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mbc1_multicart_detection() {
+        let mut rom = vec![0; 0x100000];
+        assert!(!is_mbc1_multicart(&rom));
+        // the second game's header, at the start of bank 0x10.
+        rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        assert!(is_mbc1_multicart(&rom));
+        rom.resize(0x200000, 0);
+        assert!(!is_mbc1_multicart(&rom));
+    }
+}
\ No newline at end of file
@@ -1,24 +1,181 @@
 use registers::Registers;
 use registers::RegisterFlags::{C,H,N,Z};
-use mmu::MMU;
+use mmu::{MMU, Model};
+use bus::Bus;
+use cartridge::Cartridge;
+use ppu::compat;
 
-pub struct CPU {
+pub struct CPU<B: Bus = MMU> {
     pub pc: u16,
     pub sp: u16,
     pub registers: Registers,
-    pub mmu: MMU,
-    pub ei: bool
+    pub mmu: B,
+    pub ime: bool,
+    // EI only takes effect after the instruction following it; counts down the steps until then.
+    ime_delay: u8,
+    pub halted: bool,
+    pub stopped: bool,
+    // set when HALT is executed with IME off and an interrupt already pending:
+    // the CPU fails to increment PC on the next opcode fetch.
+    halt_bug: bool,
+    // When set, the rest of the hardware is ticked on every M-cycle of an instruction
+    // so each memory access sees it at the right time; otherwise an instruction runs
+    // all at once and the hardware catches up afterwards, which is faster.
+    pub cycle_accurate: bool,
+    // M-cycles of the current step already ticked by memory accesses and idle cycles.
+    ticked: u32
 }
 
 impl CPU {
     pub fn new() -> CPU {
+        CPU::with_bus(MMU::new())
+    }
+
+    // Inserts a cartridge into whichever model it was made for.
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        let model = Model::for_cartridge(&cartridge);
+        self.load_cartridge_with_model(cartridge, model);
+    }
+
+    // Runs the boot ROM if one has been given to the MMU, and otherwise starts
+    // the cartridge from the state the boot ROM would have left.
+    pub fn load_cartridge_with_model(&mut self, cartridge: Cartridge, model: Model) {
+        let header_checksum = cartridge.header.header_checksum;
+        let title_checksum = compat::title_checksum(&cartridge);
+        self.mmu.load_cartridge_with_model(cartridge, model);
+        if self.mmu.boot_rom.is_some() {
+            return;
+        }
+        // games tell the models apart by A: 0x01 on DMG, 0xFF on MGB and 0x11 on CGB.
+        let (af, bc, de, hl) = match model {
+            Model::Dmg | Model::Mgb => {
+                let a = if model == Model::Dmg { 0x01 } else { 0xFF };
+                // H and C come from the boot ROM's header checksum, so are set unless it was 0.
+                let f = if header_checksum == 0 { 0x80 } else { 0xB0 };
+                ((a << 8) | f, 0x0013, 0x00D8, 0x014D)
+            },
+            Model::Cgb if self.mmu.cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+            // B is left holding the title checksum from the palette lookup.
+            Model::Cgb => (0x1180, (title_checksum.unwrap_or(0) as u16) << 8, 0x0008, 0x007C)
+        };
+        self.registers.set_af(af);
+        self.registers.set_bc(bc);
+        self.registers.set_de(de);
+        self.registers.set_hl(hl);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+        self.mmu.set_post_boot_state();
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    pub fn with_bus(mmu: B) -> CPU<B> {
         CPU {
             pc: 0,
             sp: 0,
             registers: Registers::new(),
-            mmu: MMU::new(),
-            ei: true
+            mmu,
+            ime: false,
+            ime_delay: 0,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            cycle_accurate: true,
+            ticked: 0
+        }
+    }
+
+    // Runs a single instruction (or interrupt dispatch) and advances the rest of the
+    // hardware to match. Returns the number of M-cycles taken.
+    pub fn step(&mut self) -> u32 {
+        if self.stopped {
+            // the system clock is stopped entirely until a selected joypad line goes low.
+            if !self.mmu.joypad_active() {
+                return 1;
+            }
+            self.stopped = false;
+        }
+        if self.mmu.stall() {
+            self.mmu.tick(1);
+            return 1;
+        }
+        if self.halted {
+            // any pending interrupt ends HALT, whether or not IME lets it be serviced.
+            if self.mmu.pending_interrupts() == 0 {
+                self.mmu.tick(1);
+                return 1;
+            }
+            self.halted = false;
+            self.mmu.set_halted(false);
+        }
+        self.ticked = 0;
+        let cycles = match self.service_interrupt() {
+            Some(cycles) => cycles,
+            None => {
+                let opcode = self.next_byte();
+                self.exec_opcode(opcode) as u32
+            }
+        };
+        if self.ime_delay > 0 {
+            self.ime_delay -= 1;
+            if self.ime_delay == 0 {
+                self.ime = true;
+            }
         }
+        self.mmu.tick(cycles - self.ticked);
+        cycles
+    }
+
+    // Advances the hardware by one M-cycle on behalf of the instruction being run.
+    fn cycle(&mut self) {
+        if self.cycle_accurate {
+            self.mmu.tick(1);
+            self.ticked += 1;
+        }
+    }
+
+    // An M-cycle with no memory access.
+    fn idle(&mut self) {
+        self.cycle();
+    }
+
+    // Each access takes an M-cycle and happens at the end of it.
+    fn read(&mut self, addr: u16) -> u8 {
+        self.cycle();
+        self.mmu.read_byte(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.cycle();
+        self.mmu.write_byte(addr, val);
+    }
+
+    // Dispatches the highest-priority pending interrupt, if IME allows it.
+    // Takes 5 M-cycles: two idle, two to push PC and one to jump to the vector.
+    fn service_interrupt(&mut self) -> Option<u32> {
+        if !self.ime || self.mmu.pending_interrupts() == 0 {
+            return None;
+        }
+        self.ime = false;
+        self.idle();
+        self.idle();
+        let pc = self.pc;
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, (pc >> 8) as u8);
+        // the interrupt is only chosen after the high byte of PC is pushed, so a push
+        // that lands on IE can cancel the dispatch, which then jumps to 0x0000 instead.
+        let pending = self.mmu.pending_interrupts();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, pc as u8);
+        if pending == 0 {
+            self.pc = 0x0000;
+        } else {
+            let bit = pending.trailing_zeros() as u16;
+            self.mmu.acknowledge_interrupt(bit as u8);
+            self.pc = 0x0040 + bit * 0x08;
+        }
+        self.idle();
+        Some(5)
     }
 
     pub fn exec_opcode(&mut self, opcode: u8) -> u8 {
@@ -33,16 +190,23 @@ impl CPU {
                             0b000 => 1, // 00 000 000 - NOP
                             0b001 => { // 00 001 000 - LD (nn), SP
                                 let addr = self.next_word();
-                                self.mmu.write_word(addr, self.sp);
+                                let sp = self.sp;
+                                self.write(addr, sp as u8);
+                                self.write(addr.wrapping_add(1), (sp >> 8) as u8);
                                 5
                             },
                             0b010 => { // 00 010 000 - STOP
-                                // stop mode to be implemented.
+                                self.pc = self.pc.wrapping_add(1); // STOP is followed by a padding byte
+                                // on CGB, STOP with KEY1 bit 0 set switches speed instead.
+                                if !self.mmu.stop() {
+                                    self.stopped = true;
+                                }
                                 1
                             },
                             0b011 => { // 00 011 000 - JR e
                                 let e = self.next_byte() as i8;
-                                self.pc += e as u16;
+                                self.pc = self.pc.wrapping_add(e as u16);
+                                self.idle();
                                 3
                             }
                             _ => { // 00 1cc 000 - conditional JR e
@@ -51,7 +215,8 @@ impl CPU {
                                     (second == 0b101 && self.registers.get_flag(Z)) ||
                                     (second == 0b110 && !self.registers.get_flag(C)) ||
                                     (second == 0b111 && self.registers.get_flag(C)) {
-                                    self.pc += e as u16;
+                                    self.pc = self.pc.wrapping_add(e as u16);
+                                    self.idle();
                                     3
                                 } else {
                                     2
@@ -71,15 +236,15 @@ impl CPU {
                             3
                         } else { // 00 rr1 001 - ADD HL, rr
                             let reg = second >> 1;
-                            let val : u16;
-                            if reg == 0b11 {
-                                val = self.sp
+                            let val = if reg == 0b11 {
+                                self.sp
                             } else {
-                                val = self.registers.get_reg16(reg);
-                            }
+                                self.registers.get_reg16(reg)
+                            };
                             let hl = self.registers.hl();
                             let result = self.alu_add16(hl, val);
                             self.registers.set_hl(result);
+                            self.idle();
                             2
                         }
                     },
@@ -92,14 +257,14 @@ impl CPU {
                             if reg > 0b01 {
                                 val = self.registers.hl();
                                 if (reg & 1) == 0 {
-                                    self.registers.set_hl(val + 1)
+                                    self.registers.set_hl(val.wrapping_add(1))
                                 } else {
-                                    self.registers.set_hl(val - 1)
+                                    self.registers.set_hl(val.wrapping_sub(1))
                                 }
                             } else {
                                 val = self.registers.get_reg16(reg);
                             }
-                            let result = self.mmu.read_byte(val);
+                            let result = self.read(val);
                             self.registers.a = result;
                             2
                         } else { // 00 rr0 010 - LD (rr), A
@@ -110,36 +275,36 @@ impl CPU {
                             if reg > 0b01 {
                                 val = self.registers.hl();
                                 if (reg & 1) == 0 {
-                                    self.registers.set_hl(val + 1);
+                                    self.registers.set_hl(val.wrapping_add(1));
                                 } else {
-                                    self.registers.set_hl(val - 1);
+                                    self.registers.set_hl(val.wrapping_sub(1));
                                 }
                             } else {
                                 val = self.registers.get_reg16(reg);
                             }
-                            let n = self.mmu.read_byte(val);
-                            self.mmu.write_byte(val, self.registers.a);
+                            self.write(val, self.registers.a);
                             2
                         }
                     },
                     0b011 => { // 00 rr1 011 - DEC rr
                                // 00 rr0 011 - INC rr
-                        let a = (1 - 2*(second & 1)) as u16;
                         let reg = second >> 1;
+                        let v = if reg == 0b11 { self.sp } else { self.registers.get_reg16(reg) };
+                        let v = if second & 1 == 0 { self.alu_inc16(v) } else { self.alu_dec16(v) };
                         if reg == 0b11 {
-                            self.sp += a;
+                            self.sp = v;
                         } else {
-                            let v = self.registers.get_reg16(reg);
-                            self.registers.set_reg16(reg, v+a);
+                            self.registers.set_reg16(reg, v);
                         }
+                        self.idle();
                         2
                     },
                     0b100 => { // 00 r 100 - INC r
                         if second == 0b110 {
                             let addr = self.registers.hl();
-                            let mut val = self.mmu.read_byte(addr);
+                            let mut val = self.read(addr);
                             val = self.alu_inc(val);
-                            self.mmu.write_byte(addr, val);
+                            self.write(addr, val);
                             3
                         } else {
                             let mut val = self.registers.get_reg(second);
@@ -151,9 +316,9 @@ impl CPU {
                     0b101 => { // 00 r 101 - DEC r
                         if second == 0b110 {
                             let addr = self.registers.hl();
-                            let mut val = self.mmu.read_byte(addr);
+                            let mut val = self.read(addr);
                             val = self.alu_dec(val);
-                            self.mmu.write_byte(addr, val);
+                            self.write(addr, val);
                             3
                         } else {
                             let mut val = self.registers.get_reg(second);
@@ -166,7 +331,7 @@ impl CPU {
                         if second == 0b110 {
                             let addr = self.registers.hl();
                             let val = self.next_byte();
-                            self.mmu.write_byte(addr, val);
+                            self.write(addr, val);
                             3
                         } else {
                             let val = self.next_byte();
@@ -205,25 +370,28 @@ impl CPU {
                                 1
                             },
                             0b100 => { // 00 100 111 - DAA
-                                let a = self.registers.a;
-                                if (a & 0x0F) > 9 || self.registers.get_flag(H) {
-                                    if self.registers.get_flag(N) {
-                                        self.registers.a -= 0x06;
-                                    } else {
-                                        self.registers.a += 0x06;
+                                // corrects A to BCD after an addition or subtraction, using
+                                // N, H and C to tell which digits need adjusting.
+                                let mut a = self.registers.a;
+                                if self.registers.get_flag(N) {
+                                    if self.registers.get_flag(C) {
+                                        a = a.wrapping_sub(0x60);
                                     }
-                                }
-                                if (a & 0xF0) > 0x90 || self.registers.get_flag(C) {
-                                    if self.registers.get_flag(N) {
-                                        self.registers.a -= 0x60;
-                                    } else {
-                                        self.registers.a += 0x60;
+                                    if self.registers.get_flag(H) {
+                                        a = a.wrapping_sub(0x06);
+                                    }
+                                } else {
+                                    if self.registers.get_flag(C) || a > 0x99 {
+                                        a = a.wrapping_add(0x60);
+                                        self.registers.set_flag(C, true);
+                                    }
+                                    if self.registers.get_flag(H) || (a & 0x0F) > 0x09 {
+                                        a = a.wrapping_add(0x06);
                                     }
-                                    self.registers.set_flag(C, true);
                                 }
+                                self.registers.a = a;
                                 self.registers.set_flag(H, false);
-                                let final_a = self.registers.a;
-                                self.registers.set_flag(Z, final_a == 0);
+                                self.registers.set_flag(Z, a == 0);
                                 1
                             },
                             0b101 => { // 00 101 111 - CPL
@@ -255,26 +423,30 @@ impl CPU {
                     0b110 => {
                         match third {
                             0b110 => { // 01 110 110 - HALT
-                                // HALT to be implemented.
+                                if !self.ime && self.mmu.pending_interrupts() != 0 {
+                                    self.halt_bug = true;
+                                } else {
+                                    self.halted = true;
+                                    self.mmu.set_halted(true);
+                                }
                                 1
                             },
                             _ => { // 01 110 r - LD (HL), r
                                 let r = self.registers.get_reg(third);
                                 let addr = self.registers.hl();
-                                self.mmu.write_byte(addr, r);
+                                self.write(addr, r);
                                 2
                             }
                         }
                     },
                     _ => { // 01 r r' - LD r, r'
-                        let val : u8;
-                        if third == 0b110 {
+                        let val = if third == 0b110 {
                             let addr = self.registers.hl();
-                            val = self.mmu.read_byte(addr);
+                            self.read(addr)
                         } else {
-                            val = self.registers.get_reg(third);
-                        }
-                        self.registers.set_reg(third, val);
+                            self.registers.get_reg(third)
+                        };
+                        self.registers.set_reg(second, val);
                         if third == 0b110 {
                             2
                         } else {
@@ -284,13 +456,12 @@ impl CPU {
                 }
             },
             0b10 => {
-                let val : u8;
-                if third == 0b110 {
+                let val = if third == 0b110 {
                     let addr = self.registers.hl();
-                    val = self.mmu.read_byte(addr);
+                    self.read(addr)
                 } else {
-                    val = self.registers.get_reg(third);
-                }
+                    self.registers.get_reg(third)
+                };
                 let a = self.registers.a;
                 if second == 0b111 {
                     self.alu_cp(a, val);
@@ -316,12 +487,14 @@ impl CPU {
                 match third {
                     0b000 => {
                         match second {
-                            0b000 ... 0b011 => {
+                            0b000..=0b011 => { // 11 0cc 000 - RET cc
+                                self.idle(); // checking the condition takes a cycle
                                 if (second == 0b000 && !self.registers.get_flag(Z)) ||
                                     (second == 0b001 && self.registers.get_flag(Z)) ||
                                     (second == 0b010 && !self.registers.get_flag(C)) ||
                                     (second == 0b011 && self.registers.get_flag(C)) {
                                     self.pc = self.pop();
+                                    self.idle();
                                     5
                                 } else {
                                     2
@@ -330,26 +503,26 @@ impl CPU {
                             0b100 => { // 11 100 000 - LD (0xFF00+n), A
                                 let addr = 0xFF00 + (self.next_byte() as u16);
                                 let a = self.registers.a;
-                                self.mmu.write_byte(addr, a);
+                                self.write(addr, a);
                                 3
                             },
                             0b101 => { // 11 101 000 - ADD SP, e
                                 let e = self.next_byte() as i8;
-                                let sp = self.sp;
-                                let newsp = self.alu_add16(sp, e as u16);
-                                self.sp = newsp;
+                                self.sp = self.alu_add_sp(e);
+                                self.idle();
+                                self.idle();
                                 4
                             },
                             0b110 => { // 11 110 000 - LD A, (0xFF00+n)
                                 let addr = 0xFF00 + (self.next_byte() as u16);
-                                self.registers.a = self.mmu.read_byte(addr);
+                                self.registers.a = self.read(addr);
                                 3
                             },
                             _ => { // 11 111 000 - LDHL SP, e
                                 let e = self.next_byte() as i8;
-                                let sp = self.sp;
-                                let spe = self.alu_add16(sp, e as u16);
+                                let spe = self.alu_add_sp(e);
                                 self.registers.set_hl(spe);
+                                self.idle();
                                 3
                             }
                         }
@@ -364,11 +537,13 @@ impl CPU {
                             },
                             0b001 => { // 11 001 001 - RET
                                 self.pc = self.pop();
+                                self.idle();
                                 4
                             },
                             0b011 => { // 11 011 001 - RETI
                                 self.pc = self.pop();
-                                self.ei = true;
+                                self.ime = true;
+                                self.idle();
                                 4
                             },
                             0b101 => { // 11 101 001 - JP (HL)
@@ -377,19 +552,21 @@ impl CPU {
                             },
                             _ => { // 11 111 001 - LD SP, HL
                                 self.sp = self.registers.hl();
+                                self.idle();
                                 2
                             }
                         }
                     },
                     0b010 => {
                         match second {
-                            0b000 ... 0b011 => {
+                            0b000..=0b011 => {
                                 let e = self.next_word();
                                 if (second == 0b000 && !self.registers.get_flag(Z)) ||
                                     (second == 0b001 && self.registers.get_flag(Z)) ||
                                     (second == 0b010 && !self.registers.get_flag(C)) ||
                                     (second == 0b011 && self.registers.get_flag(C)) {
                                     self.pc = e;
+                                    self.idle();
                                     4
                                 } else {
                                     3
@@ -398,23 +575,23 @@ impl CPU {
                             0b100 => { // 11 100 010 - LD (0xFF00+C), A
                                 let addr = 0xFF00 + (self.registers.c as u16);
                                 let a = self.registers.a;
-                                self.mmu.write_byte(addr, a);
+                                self.write(addr, a);
                                 2
                             },
                             0b101 => { // 11 101 010 - LD (nn), A
                                 let addr = self.next_word();
                                 let a = self.registers.a;
-                                self.mmu.write_byte(addr, a);
+                                self.write(addr, a);
                                 4
                             },
                             0b110 => { // 11 110 010 - LD A, (0xFF00+C)
                                 let addr = 0xFF00 + (self.registers.c as u16);
-                                self.registers.a = self.mmu.read_byte(addr);
+                                self.registers.a = self.read(addr);
                                 2
                             },
                             _ => { // 11 111 010 - LD A, (nn)
                                 let addr = self.next_word();
-                                self.registers.a = self.mmu.read_byte(addr);
+                                self.registers.a = self.read(addr);
                                 4
                             }
                         }
@@ -423,6 +600,7 @@ impl CPU {
                         match second {
                             0b000 => { // 11 000 011 - JP nn
                                 self.pc = self.next_word();
+                                self.idle();
                                 4
                             },
                             0b001 => { // 11 001 011 - prefix for two-byte opcodes.
@@ -430,11 +608,14 @@ impl CPU {
                                 self.exec_opcode2(opcode2)
                             },
                             0b110 => { // 11 110 011 - DI
-                                self.ei = false;
+                                self.ime = false;
+                                self.ime_delay = 0;
                                 1
                             },
                             0b111 => { // 11 111 011 - EI
-                                self.ei = true;
+                                if !self.ime && self.ime_delay == 0 {
+                                    self.ime_delay = 2;
+                                }
                                 1
                             },
                             _ => panic!("Unknown opcode {:x}!", opcode)
@@ -450,6 +631,7 @@ impl CPU {
                             (second == 0b010 && !self.registers.get_flag(C)) ||
                             (second == 0b011 && self.registers.get_flag(C)) {
                             let pc = self.pc;
+                            self.idle();
                             self.push(pc);
                             self.pc = e;
                             6
@@ -462,12 +644,14 @@ impl CPU {
                             _ if second & 1 == 0 => { // 11 rr0 101 - PUSH rr
                                 let reg = second >> 1;
                                 let val = self.registers.get_reg16(reg);
+                                self.idle();
                                 self.push(val);
                                 4
                             },
                             0b001 => { // 11 001 101 - CALL nn
                                 let e = self.next_word();
                                 let pc = self.pc;
+                                self.idle();
                                 self.push(pc);
                                 self.pc = e;
                                 6
@@ -496,6 +680,7 @@ impl CPU {
                     _ => { // 11  t  111 - RST t
                         let t = second * 0x08;
                         let pc = self.pc;
+                        self.idle();
                         self.push(pc);
                         self.pc = t as u16;
                         4
@@ -511,13 +696,12 @@ impl CPU {
         let third = opcode & 0b111;
         match first {
             0b00 => {
-                let val: u8;
-                if third == 0b110 {
+                let val = if third == 0b110 {
                     let addr = self.registers.hl();
-                    val = self.mmu.read_byte(addr);
+                    self.read(addr)
                 } else {
-                    val = self.registers.get_reg(third);
-                }
+                    self.registers.get_reg(third)
+                };
                 let result = match second {
                     0b000 => self.alu_rlc(val),
                     0b001 => self.alu_rrc(val),
@@ -530,7 +714,7 @@ impl CPU {
                 };
                 if third == 0b110 {
                     let addr = self.registers.hl();
-                    self.mmu.write_byte(addr, result);
+                    self.write(addr, result);
                     4
                 } else {
                     self.registers.set_reg(third, result);
@@ -538,13 +722,12 @@ impl CPU {
                 }
             },
             0b01 => { // 01 b r - BIT r, b
-                let val: u8;
-                if third == 0b110 {
+                let val = if third == 0b110 {
                     let addr = self.registers.hl();
-                    val = self.mmu.read_byte(addr);
+                    self.read(addr)
                 } else {
-                    val = self.registers.get_reg(third);
-                }
+                    self.registers.get_reg(third)
+                };
                 self.bit_info(val, second);
                 if third == 0b110 {
                     3
@@ -553,17 +736,16 @@ impl CPU {
                 }
             },
             0b10 => { // 10 b r - RES r, b
-                let val: u8;
-                if third == 0b110 {
+                let val = if third == 0b110 {
                     let addr = self.registers.hl();
-                    val = self.mmu.read_byte(addr);
+                    self.read(addr)
                 } else {
-                    val = self.registers.get_reg(third);
-                }
+                    self.registers.get_reg(third)
+                };
                 let result = self.bit_reset(val, second);
                 if third == 0b110 {
                     let addr = self.registers.hl();
-                    self.mmu.write_byte(addr, result);
+                    self.write(addr, result);
                     4
                 } else {
                     self.registers.set_reg(third, result);
@@ -571,17 +753,16 @@ impl CPU {
                 }
             },
             _ => { // 11 b r - SET r, b
-                let val: u8;
-                if third == 0b110 {
+                let val = if third == 0b110 {
                     let addr = self.registers.hl();
-                    val = self.mmu.read_byte(addr);
+                    self.read(addr)
                 } else {
-                    val = self.registers.get_reg(third);
-                }
+                    self.registers.get_reg(third)
+                };
                 let result = self.bit_set(val, second);
                 if third == 0b110 {
                     let addr = self.registers.hl();
-                    self.mmu.write_byte(addr, result);
+                    self.write(addr, result);
                     4
                 } else {
                     self.registers.set_reg(third, result);
@@ -591,14 +772,20 @@ impl CPU {
         }
     }
 
+    // Pushes the high byte first, as the hardware does.
     pub fn push(&mut self, a : u16) {
-        self.mmu.write_word(self.sp-2, a);
-        self.sp -= 2;
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, (a >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, a as u8);
     }
 
     pub fn pop(&mut self) -> u16 {
-        self.sp += 2;
-        self.mmu.read_word(self.sp-2)
+        let lo = self.read(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        let hi = self.read(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        (hi << 8) | lo
     }
 
     pub fn alu_add(&mut self, a: u8, b: u8) -> u8 {
@@ -611,41 +798,50 @@ impl CPU {
     }
 
     pub fn alu_add16(&mut self, a: u16, b: u16) -> u16 {
-        let result = a + b;
+        let result = a.wrapping_add(b);
         self.registers.set_flag(N, false);
         self.registers.set_flag(H, (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF);
-        self.registers.set_flag(C, (a as i32) + (b as i32) > 0xFFFF);
+        self.registers.set_flag(C, (a as u32) + (b as u32) > 0xFFFF);
         result
     }
 
+    // SP + e for ADD SP, e and LD HL, SP+e. The flags come from adding e to the low byte.
+    pub fn alu_add_sp(&mut self, e: i8) -> u16 {
+        let sp = self.sp;
+        let b = e as u8 as u16;
+        self.registers.set_flag(Z, false);
+        self.registers.set_flag(N, false);
+        self.registers.set_flag(H, (sp & 0x0F) + (b & 0x0F) > 0x0F);
+        self.registers.set_flag(C, (sp & 0xFF) + b > 0xFF);
+        sp.wrapping_add(e as u16)
+    }
+
     pub fn alu_adc(&mut self, a: u8, b: u8) -> u8 {
         let carry = self.registers.get_flag(C) as u8;
-        let result = self.alu_add(a, b+carry);
-        if b == 255 {
-            self.registers.set_flag(C, true);
-        }
-        if (b & 0x0F) == 0x0F {
-            self.registers.set_flag(H, true);
-        }
-        result
+        let result = (a as u16) + (b as u16) + (carry as u16);
+        self.registers.set_flag(Z, result as u8 == 0);
+        self.registers.set_flag(N, false);
+        self.registers.set_flag(H, (a & 0x0F) + (b & 0x0F) + carry > 0x0F);
+        self.registers.set_flag(C, result > 0xFF);
+        result as u8
     }
 
     pub fn alu_sub(&mut self, a: u8, b: u8) -> u8 {
-        let result = a - b;
+        let result = a.wrapping_sub(b);
         self.registers.set_flag(Z, result == 0);
         self.registers.set_flag(N, true);
-        self.registers.set_flag(H, ((a & 0x0F) as i16 - (b & 0x0F) as i16) >= 0);
-        self.registers.set_flag(C, (a as i16) - (b as i16) >= 0);
+        self.registers.set_flag(H, (a & 0x0F) < (b & 0x0F));
+        self.registers.set_flag(C, a < b);
         result
     }
 
     pub fn alu_sbc(&mut self, a: u8, b: u8) -> u8 {
         let carry = self.registers.get_flag(C) as u8;
-        let result = self.alu_sub(a, b+carry);
-        if b == 0 {
-            self.registers.set_flag(C, true);
-            self.registers.set_flag(H, true);
-        }
+        let result = a.wrapping_sub(b).wrapping_sub(carry);
+        self.registers.set_flag(Z, result == 0);
+        self.registers.set_flag(N, true);
+        self.registers.set_flag(H, (a & 0x0F) < (b & 0x0F) + carry);
+        self.registers.set_flag(C, (a as u16) < (b as u16) + (carry as u16));
         result
     }
 
@@ -681,27 +877,27 @@ impl CPU {
     }
 
     pub fn alu_inc(&mut self, a: u8) -> u8 {
-        let result = a+1;
+        let result = a.wrapping_add(1);
         self.registers.set_flag(Z, result == 0);
-        self.registers.set_flag(H, a & 0xFF == 0xFF);
+        self.registers.set_flag(H, a & 0x0F == 0x0F);
         self.registers.set_flag(N, false);
         result
     }
 
     pub fn alu_inc16(&mut self, a: u16) -> u16 {
-        a+1
+        a.wrapping_add(1)
     }
 
     pub fn alu_dec(&mut self, a: u8) -> u8 {
-        let result = a-1;
+        let result = a.wrapping_sub(1);
         self.registers.set_flag(Z, result == 0);
-        self.registers.set_flag(H, a & 0xFF == 0);
+        self.registers.set_flag(H, a & 0x0F == 0);
         self.registers.set_flag(N, true);
         result
     }
 
     pub fn alu_dec16(&mut self, a: u16) -> u16 {
-        a-1
+        a.wrapping_sub(1)
     }
 
     pub fn alu_rlc(&mut self, a: u8) -> u8 {
@@ -792,14 +988,229 @@ impl CPU {
     }
 
     pub fn next_byte(&mut self) -> u8 {
-        let result = self.mmu.read_byte(self.pc);
-        self.pc += 1;
+        let result = self.read(self.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
         result
     }
 
     pub fn next_word(&mut self) -> u16 {
-        let result = self.mmu.read_word(self.pc);
-        self.pc += 2;
-        result
+        let lo = self.next_byte() as u16;
+        let hi = self.next_byte() as u16;
+        (hi << 8) | lo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use joypad::Button;
+
+    // A CPU running `code` from WRAM.
+    fn run(code: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        for (i, &byte) in code.iter().enumerate() {
+            cpu.mmu.write_byte(0xC000 + i as u16, byte);
+        }
+        cpu.pc = 0xC000;
+        cpu.sp = 0xD000;
+        cpu
+    }
+
+    #[test]
+    fn interrupt_dispatch_by_priority() {
+        let mut cpu = run(&[0x00]);
+        cpu.ime = true;
+        cpu.mmu.inte = 0x1F;
+        cpu.mmu.intf = 0x14;
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.mmu.intf, 0x10);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.sp, 0xCFFE);
+        assert_eq!(cpu.mmu.read_word(0xCFFE), 0xC000);
+    }
+
+    #[test]
+    fn interrupt_needs_ie() {
+        let mut cpu = run(&[0x00]);
+        cpu.ime = true;
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x02;
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0xC001);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_next_instruction() {
+        // EI; NOP; NOP
+        let mut cpu = run(&[0xFB, 0x00, 0x00]);
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+        cpu.step();
+        assert_eq!(cpu.pc, 0xC001);
+        cpu.step();
+        assert_eq!(cpu.pc, 0xC002);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0040);
+    }
+
+    #[test]
+    fn di_right_after_ei_blocks_interrupts() {
+        // EI; DI; NOP
+        let mut cpu = run(&[0xFB, 0xF3, 0x00]);
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.pc, 0xC003);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        // RETI, returning to NOPs at 0xC010
+        let mut cpu = run(&[0xD9]);
+        cpu.mmu.write_word(0xCFFE, 0xC010);
+        cpu.sp = 0xCFFE;
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+        cpu.step();
+        assert!(cpu.ime);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0040);
+    }
+    #[test]
+    fn halt_waits_for_interrupt() {
+        // HALT; NOP
+        let mut cpu = run(&[0x76, 0x00]);
+        cpu.ime = true;
+        cpu.mmu.inte = 0x04;
+        cpu.step();
+        assert!(cpu.halted);
+        for _ in 0..10 {
+            assert_eq!(cpu.step(), 1);
+        }
+        assert_eq!(cpu.pc, 0xC001);
+        cpu.mmu.intf = 0x04;
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.mmu.read_word(cpu.sp), 0xC001);
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        // HALT; INC A
+        let mut cpu = run(&[0x76, 0x3C]);
+        cpu.mmu.inte = 0x01;
+        cpu.step();
+        cpu.step();
+        assert!(cpu.halted);
+        cpu.mmu.intf = 0x01;
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.mmu.intf, 0x01);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT; INC A; NOP, with an interrupt already pending and IME off
+        let mut cpu = run(&[0x76, 0x3C, 0x00]);
+        cpu.registers.set_af(0);
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.af() >> 8, 2);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+
+    #[test]
+    fn stop_waits_for_joypad() {
+        // STOP 0; NOP
+        let mut cpu = run(&[0x10, 0x00, 0x00]);
+        cpu.step();
+        assert!(cpu.stopped);
+        cpu.mmu.write_byte(0xFF00, 0x20);
+        for _ in 0..10 {
+            cpu.step();
+        }
+        assert_eq!(cpu.pc, 0xC002);
+        cpu.mmu.joypad.press(Button::Right);
+        cpu.step();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, 0xC003);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut cpu = run(&[0x10, 0x00, 0x00]);
+        cpu.mmu.cgb = true;
+        cpu.mmu.write_byte(0xFF4D, 0x01);
+        cpu.step();
+        assert!(!cpu.stopped);
+        assert!(cpu.mmu.double_speed);
+        assert_eq!(cpu.mmu.read_byte(0xFF4D), 0xFE);
+        // the CPU stalls while the clock settles.
+        for _ in 0..2050 {
+            cpu.step();
+        }
+        assert_eq!(cpu.pc, 0xC002);
+        cpu.step();
+        assert_eq!(cpu.pc, 0xC003);
+    }
+    // TAC 0x05 ticks TIMA on the falling edge of counter bit 3, every 4 M-cycles.
+    // `counter` places that edge relative to the start of the next instruction.
+    fn run_with_timer(code: &[u8], counter: u16, cycle_accurate: bool) -> CPU {
+        let mut cpu = run(code);
+        cpu.cycle_accurate = cycle_accurate;
+        cpu.mmu.write_byte(0xFF07, 0x05);
+        cpu.mmu.timer.set_counter(counter);
+        cpu
+    }
+
+    #[test]
+    fn read_sees_timer_mid_instruction() {
+        // LDH A,(0x05) reads TIMA on its third M-cycle; TIMA ticks on the second.
+        for &(accurate, expected) in &[(true, 1), (false, 0)] {
+            let mut cpu = run_with_timer(&[0xF0, 0x05], 0x0008, accurate);
+            assert_eq!(cpu.step(), 3);
+            assert_eq!(cpu.registers.a, expected);
+            assert_eq!(cpu.mmu.read_byte(0xFF05), 1);
+        }
+    }
+
+    #[test]
+    fn read_modify_write_lands_after_timer_tick() {
+        // INC (HL) on TIMA reads it on M-cycle 2 and writes on 3, as TIMA ticks.
+        // Cycle-accurate, the write lands after the tick and replaces it; caught up
+        // afterwards, the tick adds to the written value.
+        for &(accurate, expected) in &[(true, 1), (false, 2)] {
+            let mut cpu = run_with_timer(&[0x34], 0x0004, accurate);
+            cpu.registers.h = 0xFF;
+            cpu.registers.l = 0x05;
+            assert_eq!(cpu.step(), 3);
+            assert_eq!(cpu.mmu.read_byte(0xFF05), expected);
+        }
+    }
+
+    #[test]
+    fn read_sees_ppu_mode_mid_instruction() {
+        // LDH A,(0x41) 72 dots into a line: mode 3 starts at dot 80, before the read.
+        for &(accurate, expected) in &[(true, 3), (false, 2)] {
+            let mut cpu = run(&[0xF0, 0x41]);
+            cpu.cycle_accurate = accurate;
+            cpu.mmu.write_byte(0xFF40, 0x91);
+            cpu.mmu.tick(18);
+            cpu.step();
+            assert_eq!(cpu.registers.a & 0x03, expected);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,194 @@
+use cpu::CPU;
+use joypad::Button;
+use apu::CLOCK_RATE;
+use ppu::{SCREEN_WIDTH, SCREEN_HEIGHT, DOTS_PER_FRAME};
+use sdl2;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture};
+use sdl2::video::FullscreenType;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 48000;
+// Audio queued beyond this (in seconds) is dropped rather than letting latency build up.
+const MAX_AUDIO_LATENCY: f64 = 0.1;
+const MAX_SCALE: u32 = 8;
+
+fn key_button(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::Right => Some(Button::Right),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+        Keycode::X => Some(Button::A),
+        Keycode::Z => Some(Button::B),
+        Keycode::Backspace | Keycode::RShift => Some(Button::Select),
+        Keycode::Return => Some(Button::Start),
+        _ => None
+    }
+}
+
+pub struct Frontend {
+    renderer: Renderer<'static>,
+    texture: Texture,
+    audio: Option<AudioQueue<f32>>,
+    events: sdl2::EventPump,
+    scale: u32,
+    // scale the picture by whole numbers only, leaving a border, instead of filling the window
+    integer_scale: bool
+}
+
+impl Frontend {
+    pub fn new(title: &str, scale: u32) -> Result<Frontend, String> {
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+        let window = video.window(title, SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let renderer = window.renderer().accelerated().build().map_err(|e| e.to_string())?;
+        let texture = renderer.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|e| e.to_string())?;
+        // carry on without sound if there's no audio device
+        let audio = sdl.audio().and_then(|audio| {
+            let spec = AudioSpecDesired {
+                freq: Some(SAMPLE_RATE as i32),
+                channels: Some(2),
+                samples: Some(1024)
+            };
+            audio.open_queue::<f32>(None, &spec)
+        });
+        let audio = match audio {
+            Ok(queue) => {
+                queue.resume();
+                Some(queue)
+            },
+            Err(e) => {
+                eprintln!("audio disabled: {}", e);
+                None
+            }
+        };
+        let events = sdl.event_pump()?;
+        Ok(Frontend {
+            renderer,
+            texture,
+            audio,
+            events,
+            scale,
+            integer_scale: true
+        })
+    }
+
+    // Runs until the window is closed.
+    pub fn run(&mut self, cpu: &mut CPU) {
+        cpu.mmu.apu.set_sample_rate(SAMPLE_RATE);
+        // 70224 dots at 4194304 Hz, about 59.73 frames per second
+        let frame_time = Duration::from_nanos(DOTS_PER_FRAME as u64 * 1_000_000_000 / CLOCK_RATE as u64);
+        let mut next_frame = Instant::now();
+        // budgeting dots rather than waiting for VBlank keeps pacing steady while the LCD is off;
+        // whatever the last instruction overran by is taken off the next frame.
+        let mut dots = 0;
+        while self.handle_events(cpu) {
+            while dots < DOTS_PER_FRAME {
+                dots += cpu.step() * cpu.mmu.dots_per_cycle();
+            }
+            dots -= DOTS_PER_FRAME;
+            if cpu.mmu.ppu.frame_ready {
+                cpu.mmu.ppu.frame_ready = false;
+                self.texture.update(None, &cpu.mmu.ppu.framebuffer, SCREEN_WIDTH * 3).unwrap();
+            }
+            self.present();
+            self.queue_audio(cpu);
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                // too far behind to catch up; don't try to run the missed frames all at once
+                next_frame = now;
+            }
+        }
+    }
+
+    // Returns false once the user has asked to quit.
+    fn handle_events(&mut self, cpu: &mut CPU) -> bool {
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    match key {
+                        Keycode::F11 => self.toggle_fullscreen(),
+                        Keycode::F10 => self.integer_scale = !self.integer_scale,
+                        Keycode::Equals => self.set_scale(self.scale + 1),
+                        Keycode::Minus => self.set_scale(self.scale - 1),
+                        _ => if let Some(button) = key_button(key) {
+                            cpu.mmu.joypad.press(button);
+                        }
+                    }
+                },
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(button) = key_button(key) {
+                        cpu.mmu.joypad.release(button);
+                    }
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        if let Some(window) = self.renderer.window_mut() {
+            let state = match window.fullscreen_state() {
+                FullscreenType::Off => FullscreenType::Desktop,
+                _ => FullscreenType::Off
+            };
+            if let Err(e) = window.set_fullscreen(state) {
+                eprintln!("could not change fullscreen mode: {}", e);
+            }
+        }
+    }
+
+    fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.clamp(1, MAX_SCALE);
+        if let Some(window) = self.renderer.window_mut() {
+            if window.fullscreen_state() == FullscreenType::Off {
+                let _ = window.set_size(SCREEN_WIDTH as u32 * self.scale, SCREEN_HEIGHT as u32 * self.scale);
+            }
+        }
+    }
+
+    // Draws the screen centred in the window, as large as it fits.
+    fn present(&mut self) {
+        let (width, height) = self.renderer.output_size().unwrap_or((SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32));
+        let (w, h) = if self.integer_scale {
+            let scale = (width / SCREEN_WIDTH as u32).min(height / SCREEN_HEIGHT as u32).max(1);
+            (SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
+        } else if width * (SCREEN_HEIGHT as u32) < height * (SCREEN_WIDTH as u32) {
+            (width, width * SCREEN_HEIGHT as u32 / SCREEN_WIDTH as u32)
+        } else {
+            (height * SCREEN_WIDTH as u32 / SCREEN_HEIGHT as u32, height)
+        };
+        let dst = Rect::new((width as i32 - w as i32) / 2, (height as i32 - h as i32) / 2, w, h);
+        self.renderer.set_draw_color(Color::RGB(0, 0, 0));
+        self.renderer.clear();
+        let _ = self.renderer.copy(&self.texture, None, Some(dst));
+        self.renderer.present();
+    }
+
+    fn queue_audio(&mut self, cpu: &mut CPU) {
+        if let Some(ref queue) = self.audio {
+            let queued = queue.size() as f64 / (4.0 * 2.0 * SAMPLE_RATE as f64);
+            if queued < MAX_AUDIO_LATENCY {
+                queue.queue(&cpu.mmu.apu.samples);
+            }
+        }
+        cpu.mmu.apu.samples.clear();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,125 @@
+use cpu::CPU;
+use ppu::{SCREEN_WIDTH, SCREEN_HEIGHT, DOTS_PER_FRAME};
+use png;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+// Something to watch for while running without a display.
+pub enum Condition {
+    // PC reaches this address
+    Pc(u16),
+    // this byte sequence shows up in the serial output
+    Serial(Vec<u8>),
+    // the byte at this address changes from what it was when the run started
+    MemoryChange(u16),
+    // the next instruction to execute has this opcode (Mooneye's tests end on LD B,B)
+    Opcode(u8)
+}
+
+impl Condition {
+    // `initial` is what the watched byte held when the run started, and `seen` how
+    // much of the serial output has already been searched. Memory is peeked, so a
+    // DMA in progress or a locked VRAM doesn't look like a change.
+    fn met(&self, cpu: &CPU, initial: u8, seen: usize) -> bool {
+        match *self {
+            Condition::Pc(addr) => cpu.pc == addr,
+            Condition::Serial(ref pattern) => {
+                let output = &cpu.mmu.serial.output;
+                if pattern.is_empty() || output.len() == seen {
+                    return false;
+                }
+                // a new match has to end in the new bytes.
+                let start = seen.min(output.len()).saturating_sub(pattern.len() - 1);
+                output[start..].windows(pattern.len()).any(|w| w == &pattern[..])
+            },
+            Condition::MemoryChange(addr) => cpu.mmu.peek_byte(addr) != initial,
+            Condition::Opcode(opcode) => cpu.mmu.peek_byte(cpu.pc) == opcode
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // the condition at this index in the list was met
+    Met(usize),
+    // ran out of frames first
+    FrameLimit
+}
+
+// Runs `cpu` for up to `frames` frames (counted in dots, so the LCD can be off),
+// stopping early at the first condition that is met.
+pub fn run(cpu: &mut CPU, frames: u32, conditions: &[Condition]) -> Outcome {
+    let initial: Vec<u8> = conditions.iter().map(|c| match *c {
+        Condition::MemoryChange(addr) => cpu.mmu.peek_byte(addr),
+        _ => 0
+    }).collect();
+    let budget = frames as u64 * DOTS_PER_FRAME as u64;
+    let mut dots = 0u64;
+    let mut seen = 0;
+    while dots < budget {
+        dots += (cpu.step() * cpu.mmu.dots_per_cycle()) as u64;
+        // nothing plays the audio, so don't let it pile up
+        cpu.mmu.apu.samples.clear();
+        for (i, condition) in conditions.iter().enumerate() {
+            if condition.met(cpu, initial[i], seen) {
+                return Outcome::Met(i);
+            }
+        }
+        seen = cpu.mmu.serial.output.len();
+    }
+    Outcome::FrameLimit
+}
+
+// Writes the PPU's framebuffer out as an RGB PNG.
+pub fn write_png<P: AsRef<Path>>(cpu: &CPU, path: P) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&cpu.mmu.ppu.framebuffer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_match_across_new_output() {
+        let mut cpu = CPU::new();
+        let condition = Condition::Serial(b"Passed".to_vec());
+        cpu.mmu.serial.output.extend_from_slice(b"Pas");
+        assert!(!condition.met(&cpu, 0, 0));
+        cpu.mmu.serial.output.extend_from_slice(b"sed");
+        assert!(condition.met(&cpu, 0, 3));
+    }
+
+    #[test]
+    fn serial_only_checks_new_output() {
+        let mut cpu = CPU::new();
+        let condition = Condition::Serial(b"Passed".to_vec());
+        cpu.mmu.serial.output.extend_from_slice(b"Passed\n");
+        assert!(condition.met(&cpu, 0, 0));
+        assert!(!condition.met(&cpu, 0, 7));
+        cpu.mmu.serial.output.extend_from_slice(b"x");
+        assert!(!condition.met(&cpu, 0, 7));
+    }
+    #[test]
+    fn memory_conditions_ignore_oam_dma() {
+        let mut cpu = CPU::new();
+        for addr in 0xC000..0xC0A0 {
+            cpu.mmu.write_byte(addr, 0x11);
+        }
+        cpu.mmu.write_byte(0xC100, 0x40);
+        cpu.pc = 0xC100;
+        cpu.mmu.write_byte(0xFF46, 0xC0);
+        cpu.mmu.tick(2);
+        // the CPU would see the byte being copied instead.
+        assert_eq!(cpu.mmu.read_byte(0xC100), 0x11);
+        assert!(!Condition::MemoryChange(0xC100).met(&cpu, 0x40, 0));
+        assert!(Condition::Opcode(0x40).met(&cpu, 0, 0));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,152 @@
+use mmu::INT_JOYPAD;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
+
+impl Button {
+    // Bit in `Joypad::pressed`: the low nibble is the d-pad (P14), the high nibble the buttons (P15),
+    // each in the order they appear on P10-P13.
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80
+        }
+    }
+}
+
+pub struct Joypad {
+    select: u8, // P14/P15 select lines (bits 4-5 of P1)
+    pressed: u8,
+    pub interrupt: u8
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: 0,
+            interrupt: 0
+        }
+    }
+
+    // P10-P13 as the CPU sees them: a line is pulled low if any selected row has that key held.
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & 0x0F
+    }
+
+    // Any input line going from high to low requests the joypad interrupt, whether
+    // from a key press or from selecting a row with a key already held.
+    fn update(&mut self, old_lines: u8) {
+        if old_lines & !self.lines() != 0 {
+            self.interrupt |= INT_JOYPAD;
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        let old = self.lines();
+        self.pressed |= button.mask();
+        self.update(old);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write_byte(&mut self, val: u8) {
+        let old = self.lines();
+        self.select = val & 0x30;
+        self.update(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_selected_reads_high() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::A);
+        joypad.press(Button::Right);
+        assert_eq!(joypad.read_byte(), 0xFF);
+    }
+
+    #[test]
+    fn rows_are_selected_by_p14_and_p15() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Down);
+        joypad.press(Button::Start);
+        joypad.write_byte(0x20); // P14 low: d-pad
+        assert_eq!(joypad.read_byte(), 0xE7);
+        joypad.write_byte(0x10); // P15 low: buttons
+        assert_eq!(joypad.read_byte(), 0xD7);
+        joypad.press(Button::B);
+        assert_eq!(joypad.read_byte(), 0xD5);
+        // with both rows selected the lines are ANDed together.
+        joypad.press(Button::Left);
+        joypad.write_byte(0x00);
+        assert_eq!(joypad.read_byte(), 0xC5);
+        joypad.release(Button::Down);
+        joypad.release(Button::Start);
+        assert_eq!(joypad.read_byte(), 0xCD);
+    }
+
+    #[test]
+    fn press_on_selected_row_interrupts() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0x10);
+        joypad.press(Button::Up);
+        assert_eq!(joypad.interrupt, 0);
+        joypad.press(Button::Select);
+        assert_eq!(joypad.interrupt, INT_JOYPAD);
+    }
+
+    #[test]
+    fn interrupt_only_on_high_to_low() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0x20);
+        joypad.press(Button::Right);
+        joypad.interrupt = 0;
+        // the line is already low, and releases never interrupt.
+        joypad.press(Button::A);
+        joypad.release(Button::Right);
+        assert_eq!(joypad.interrupt, 0);
+    }
+
+    #[test]
+    fn selecting_row_with_key_held_interrupts() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Start);
+        assert_eq!(joypad.interrupt, 0);
+        joypad.write_byte(0x20);
+        assert_eq!(joypad.interrupt, 0);
+        joypad.write_byte(0x10);
+        assert_eq!(joypad.interrupt, INT_JOYPAD);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::new_without_default)]
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+extern crate png;
+pub mod cpu;
+pub mod bus;
+pub mod registers;
+pub mod mmu;
+pub mod cartridge;
+pub mod mbc;
+pub mod timer;
+pub mod ppu;
+pub mod apu;
+pub mod joypad;
+pub mod serial;
+pub mod headless;
+#[cfg(feature = "sdl")]
+pub mod frontend;
\ No newline at end of file
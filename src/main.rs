@@ -1,10 +1,105 @@
-extern crate sdl2;
-mod cpu;
-mod registers;
-mod mmu;
-use cpu::CPU;
+extern crate gb_em;
+use gb_em::cpu::CPU;
+use gb_em::cartridge::Cartridge;
+use gb_em::mmu::Model;
+use gb_em::ppu::FifoRenderer;
+use gb_em::ppu::compat;
+use gb_em::apu::HighPass;
+use gb_em::frontend::Frontend;
+use std::env;
+use std::fs;
+use std::process;
 
 
 fn main() {
-    
+    let mut path = None;
+    let mut fifo = false;
+    let mut scale = 3;
+    let mut model = None;
+    let mut palette = None;
+    let mut boot_rom = None;
+    let mut high_pass = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fifo" => fifo = true,
+            "--scale" => scale = match args.next().and_then(|s| s.parse().ok()) {
+                Some(scale) if scale > 0 => scale,
+                _ => usage()
+            },
+            "--model" => model = match args.next().and_then(|s| Model::from_name(&s)) {
+                Some(model) => Some(model),
+                None => usage()
+            },
+            "--boot-rom" => boot_rom = Some(args.next().unwrap_or_else(|| usage())),
+            // picks a DMG game's colours on a CGB, as holding a button combination at boot would
+            "--palette" => palette = match args.next().and_then(|s| compat::key_combination(&s)) {
+                Some(combination) => Some(combination),
+                None => usage()
+            },
+            // overrides the output filter of the model being emulated
+            "--high-pass" => high_pass = match args.next().and_then(|s| HighPass::from_name(&s)) {
+                Some(filter) => Some(filter),
+                None => usage()
+            },
+            _ => path = Some(arg)
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => usage()
+    };
+    let cartridge = match Cartridge::from_file(&path) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let title = format!("GBEm - {}", cartridge.header.title);
+    let model = match (model, palette) {
+        (Some(model), _) => model,
+        (None, Some(_)) => Model::Cgb,
+        (None, None) => Model::for_cartridge(&cartridge)
+    };
+    let mut cpu = CPU::new();
+    if let Some(boot_rom) = boot_rom {
+        cpu.mmu.boot_rom = match fs::read(&boot_rom) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                eprintln!("{}: {}", boot_rom, e);
+                process::exit(1);
+            }
+        };
+    }
+    cpu.load_cartridge_with_model(cartridge, model);
+    if let Some(combination) = palette {
+        if cpu.mmu.ppu.video.compat {
+            compat::load_combination(&mut cpu.mmu.ppu.video, combination);
+        } else {
+            // with a boot ROM, hold the buttons during the logo instead
+            eprintln!("--palette needs a DMG game on a CGB and no boot ROM; ignoring it");
+        }
+    }
+    if let Some(filter) = high_pass {
+        cpu.mmu.apu.set_high_pass(filter);
+    }
+    if fifo {
+        cpu.mmu.ppu.set_renderer(Box::new(FifoRenderer::new()));
+    }
+    let mut frontend = match Frontend::new(&title, scale) {
+        Ok(frontend) => frontend,
+        Err(e) => {
+            eprintln!("could not start SDL: {}", e);
+            process::exit(1);
+        }
+    };
+    frontend.run(&mut cpu);
+}
+
+fn usage() -> ! {
+    eprintln!("usage: gb_em [--fifo] [--scale N] [--model dmg|mgb|cgb] [--boot-rom FILE] [--palette COMBO] \
+               [--high-pass off|dmg|cgb] <rom>");
+    eprintln!("  COMBO is the button combination that picks a palette at boot, e.g. left+a");
+    process::exit(1);
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+use mbc::MBC;
+
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8, // 5-bit ROM bank register (0x2000-0x3FFF)
+    bank2: u8, // 2-bit upper ROM / RAM bank register (0x4000-0x5FFF)
+    mode: u8,  // banking mode select (0x6000-0x7FFF)
+    // MBC1M multicarts wire BANK2 to ROM address lines 18-19 instead of 19-20,
+    // so only the low four bits of BANK1 are used.
+    multicart: bool
+}
+
+impl MBC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, multicart: bool) -> MBC1 {
+        MBC1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: 0,
+            multicart
+        }
+    }
+
+    fn bank2_shift(&self) -> u32 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_bank_lo(&self) -> usize {
+        if self.mode == 1 {
+            (self.bank2 as usize) << self.bank2_shift()
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank_hi(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        ((self.bank2 as usize) << self.bank2_shift()) | (bank1 as usize)
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.mode == 1 { self.bank2 as usize } else { 0 };
+        ((bank << 13) | (addr as usize & 0x1FFF)) & (self.ram.len() - 1)
+    }
+}
+
+impl MBC for MBC1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { self.rom_bank_lo() } else { self.rom_bank_hi() };
+        let offset = (bank << 14) | (addr as usize & 0x3FFF);
+        self.rom[offset & (self.rom.len() - 1)]
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // bank 0 can't be selected here; the zero check sees all five bits,
+                // which is why banks 0x20/0x40/0x60 are unreachable in mode 0.
+                self.bank1 = val & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            _ => self.mode = val & 0x01
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = val;
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ROM whose banks each start with their own bank number.
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn rom_bank_zero_selects_one() {
+        let mut mbc = MBC1::new(rom(32), 0, false);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // only five bits are written, so 0x20 also reads as bank 0.
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn bank2_remaps_bank_zero_area_in_mode_1() {
+        let mut mbc = MBC1::new(rom(128), 0, false);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        // banks 0x20/0x40/0x60 can't be reached at 0x4000; 0x21 comes up instead.
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x60);
+        assert_eq!(mbc.read_rom(0x4000), 0x61);
+    }
+
+    #[test]
+    fn ram_enable_and_banking() {
+        let mut mbc = MBC1::new(rom(4), 0x8000, false);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        // in mode 0 BANK2 doesn't touch RAM; in mode 1 it picks the bank.
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x34);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicart_uses_four_bank1_bits() {
+        let mut mbc = MBC1::new(rom(64), 0, true);
+        mbc.write_rom(0x2000, 0x13);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x23);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+}
\ No newline at end of file
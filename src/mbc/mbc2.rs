@@ -0,0 +1,98 @@
+use mbc::MBC;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: [u8; 512], // built-in 512x4-bit RAM; only the low nibble of each byte is stored
+    ram_enabled: bool,
+    rom_bank: u8
+}
+
+impl MBC2 {
+    pub fn new(rom: Vec<u8>) -> MBC2 {
+        MBC2 {
+            rom,
+            ram: [0; 512],
+            ram_enabled: false,
+            rom_bank: 1
+        }
+    }
+}
+
+impl MBC for MBC2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank as usize };
+        let offset = (bank << 14) | (addr as usize & 0x3FFF);
+        self.rom[offset & (self.rom.len() - 1)]
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        // both registers share 0x0000-0x3FFF; address bit 8 picks which one is written.
+        if addr >= 0x4000 {
+            return;
+        }
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = val & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = val & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // only nine address lines are decoded, so the RAM repeats through 0xA000-0xBFFF.
+        self.ram[(addr & 0x1FF) as usize] | 0xF0
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.ram[(addr & 0x1FF) as usize] = val & 0x0F;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut rom = vec![0; 0x40000];
+        for bank in 0..16 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut mbc = MBC2::new(rom);
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // bit 8 clear: the RAM enable register, which leaves the bank alone.
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xF2);
+    }
+
+    #[test]
+    fn ram_is_four_bits_and_repeats() {
+        let mut mbc = MBC2::new(vec![0; 0x8000]);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA1FF, 0xAB);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xFB);
+        assert_eq!(mbc.read_ram(0xA3FF), 0xFB);
+        assert_eq!(mbc.read_ram(0xBFFF), 0xFB);
+    }
+}
\ No newline at end of file
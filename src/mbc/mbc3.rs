@@ -0,0 +1,388 @@
+use mbc::MBC;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// T-cycles per emulated second (the RTC has its own 32.768 kHz crystal, but it
+// divides down to the same one-second tick).
+const CYCLES_PER_SECOND: u32 = 4194304;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RtcClock {
+    // time only passes while the emulator is running, in lockstep with the CPU.
+    Emulated,
+    // time follows the host clock, including while the emulator is closed.
+    WallClock
+}
+
+#[derive(Clone, Copy)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days_low: u8,
+    pub days_high: u8 // bit 0: day bit 8, bit 6: halt, bit 7: day counter carry
+}
+
+impl RtcRegisters {
+    fn new() -> RtcRegisters {
+        RtcRegisters {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days_low: 0,
+            days_high: 0
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.days_high & 0x40 != 0
+    }
+
+    fn days(&self) -> u16 {
+        (((self.days_high & 1) as u16) << 8) | (self.days_low as u16)
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & 0xFE) | ((days >> 8) as u8 & 1);
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days_low,
+            _ => self.days_high
+        }
+    }
+
+    fn write_footer(&self, out: &mut Vec<u8>) {
+        for &reg in &[self.seconds, self.minutes, self.hours, self.days_low, self.days_high] {
+            out.extend_from_slice(&[reg, 0, 0, 0]);
+        }
+    }
+
+    fn read_footer(data: &[u8]) -> RtcRegisters {
+        let mut regs = RtcRegisters::new();
+        for reg in 0..5 {
+            regs.write(0x08 + reg as u8, data[reg * 4]);
+        }
+        regs
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days_low = val,
+            _ => self.days_high = val & 0xC1
+        }
+    }
+
+    // Counters are only as wide as their registers, so out-of-range values
+    // (e.g. 62 seconds) count up to the register width and wrap to 0 without
+    // carrying into the next counter.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        let days = self.days() + 1;
+        if days == 512 {
+            self.days_high |= 0x80;
+        }
+        self.set_days(days & 0x1FF);
+    }
+
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+        // step manually until every counter holds a sane value, then do the rest arithmetically.
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let total = seconds + (self.seconds as u64) + 60 * (self.minutes as u64) +
+            3600 * (self.hours as u64) + 86400 * (self.days() as u64);
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days >= 512 {
+            self.days_high |= 0x80;
+        }
+        self.set_days((days % 512) as u16);
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool, // also gates access to the RTC registers
+    rom_bank: u8,
+    ram_bank: u8, // 0x00-0x03 select a RAM bank, 0x08-0x0C an RTC register
+    has_rtc: bool,
+    clock: RtcClock,
+    rtc: RtcRegisters,
+    latched: RtcRegisters,
+    latch_armed: bool,
+    subsecond_cycles: u32,
+    last_update: u64
+}
+
+impl MBC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool, clock: RtcClock) -> MBC3 {
+        MBC3 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rtc,
+            clock,
+            rtc: RtcRegisters::new(),
+            latched: RtcRegisters::new(),
+            latch_armed: false,
+            subsecond_cycles: 0,
+            last_update: unix_time()
+        }
+    }
+
+    // Brings the live registers up to date with the host clock.
+    fn sync_wall_clock(&mut self) {
+        if self.clock != RtcClock::WallClock {
+            return;
+        }
+        let now = unix_time();
+        if now > self.last_update {
+            self.rtc.advance(now - self.last_update);
+        }
+        self.last_update = now;
+    }
+
+    fn rtc_selected(&self) -> bool {
+        self.has_rtc && self.ram_bank >= 0x08 && self.ram_bank <= 0x0C
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (((self.ram_bank as usize) << 13) | (addr as usize & 0x1FFF)) & (self.ram.len() - 1)
+    }
+}
+
+impl MBC for MBC3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank as usize };
+        let offset = (bank << 14) | (addr as usize & 0x3FFF);
+        self.rom[offset & (self.rom.len() - 1)]
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = val & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            _ => {
+                // writing 0x00 then 0x01 copies the live registers into the latch.
+                if self.latch_armed && val == 0x01 && self.has_rtc {
+                    self.sync_wall_clock();
+                    self.latched = self.rtc;
+                }
+                self.latch_armed = val == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if self.rtc_selected() {
+            return self.latched.read(self.ram_bank);
+        }
+        if self.ram_bank > 0x03 || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if self.rtc_selected() {
+            self.sync_wall_clock();
+            if self.ram_bank == 0x08 {
+                self.subsecond_cycles = 0;
+            }
+            self.rtc.write(self.ram_bank, val);
+            return;
+        }
+        if self.ram_bank > 0x03 || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = val;
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if !self.has_rtc || self.clock != RtcClock::Emulated || self.rtc.halted() {
+            return;
+        }
+        self.subsecond_cycles += cycles;
+        while self.subsecond_cycles >= CYCLES_PER_SECOND {
+            self.subsecond_cycles -= CYCLES_PER_SECOND;
+            self.rtc.tick_second();
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // The footer VBA-M, BGB and most other emulators append: the live and latched
+    // registers as ten little-endian u32s, then the UNIX time they were saved at
+    // as a u64 (older files use a u32, making the footer 44 bytes instead of 48).
+    fn rtc_footer(&mut self) -> Option<Vec<u8>> {
+        if !self.has_rtc {
+            return None;
+        }
+        self.sync_wall_clock();
+        let mut footer = Vec::with_capacity(48);
+        self.rtc.write_footer(&mut footer);
+        self.latched.write_footer(&mut footer);
+        let timestamp = unix_time();
+        for i in 0..8 {
+            footer.push((timestamp >> (8 * i)) as u8);
+        }
+        Some(footer)
+    }
+
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if !self.has_rtc || (footer.len() != 44 && footer.len() != 48) {
+            return;
+        }
+        self.rtc = RtcRegisters::read_footer(&footer[0..20]);
+        self.latched = RtcRegisters::read_footer(&footer[20..40]);
+        let timestamp = footer[40..].iter().rev().fold(0u64, |t, &b| (t << 8) | (b as u64));
+        // with the host clock driving the RTC, the time the emulator spent closed still counts.
+        self.last_update = timestamp;
+        self.sync_wall_clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mbc3() -> MBC3 {
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0x8000, true, RtcClock::Emulated);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn set_rtc(mbc: &mut MBC3, regs: [u8; 5]) {
+        for (i, &val) in regs.iter().enumerate() {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            mbc.write_ram(0xA000, val);
+        }
+    }
+
+    fn latch(mbc: &mut MBC3) -> [u8; 5] {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        let mut regs = [0; 5];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            *reg = mbc.read_ram(0xA000);
+        }
+        regs
+    }
+
+    #[test]
+    fn rom_bank_zero_selects_one() {
+        let mut rom = vec![0; 0x200000];
+        rom[0x4000] = 1;
+        rom[0x7F * 0x4000] = 0x7F;
+        let mut mbc = MBC3::new(rom, 0, false, RtcClock::Emulated);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+    }
+
+    #[test]
+    fn rtc_rolls_over_into_day_carry() {
+        let mut mbc = mbc3();
+        set_rtc(&mut mbc, [59, 59, 23, 0xFF, 0x01]);
+        mbc.tick(CYCLES_PER_SECOND - 1);
+        assert_eq!(latch(&mut mbc), [59, 59, 23, 0xFF, 0x01]);
+        mbc.tick(1);
+        assert_eq!(latch(&mut mbc), [0, 0, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn rtc_out_of_range_values_wrap_at_register_width() {
+        let mut mbc = mbc3();
+        set_rtc(&mut mbc, [63, 0, 0, 0, 0]);
+        mbc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut mbc), [0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rtc_advance_matches_ticking() {
+        let mut regs = RtcRegisters::new();
+        regs.write(0x08, 58);
+        regs.write(0x0A, 25);
+        let mut ticked = regs;
+        regs.advance(100000);
+        for _ in 0..100000 {
+            ticked.tick_second();
+        }
+        for reg in 0x08..0x0D {
+            assert_eq!(regs.read(reg), ticked.read(reg));
+        }
+    }
+
+    #[test]
+    fn rtc_halt_and_latch() {
+        let mut mbc = mbc3();
+        set_rtc(&mut mbc, [10, 0, 0, 0, 0x40]);
+        mbc.tick(CYCLES_PER_SECOND * 5);
+        assert_eq!(latch(&mut mbc)[0], 10);
+        set_rtc(&mut mbc, [10, 0, 0, 0, 0x00]);
+        mbc.tick(CYCLES_PER_SECOND * 5);
+        // without a new latch the registers still read the old time.
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 10);
+        assert_eq!(latch(&mut mbc)[0], 15);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,141 @@
+use mbc::MBC;
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16, // 9 bits, split across 0x2000-0x2FFF (low) and 0x3000-0x3FFF (bit 8)
+    ram_bank: u8,
+    // on rumble carts bit 3 of the RAM bank register drives the motor instead of RAM A16.
+    has_rumble: bool,
+    rumble: bool
+}
+
+impl MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> MBC5 {
+        MBC5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (((self.ram_bank as usize) << 13) | (addr as usize & 0x1FFF)) & (self.ram.len() - 1)
+    }
+}
+
+impl MBC for MBC5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        // unlike MBC1/MBC3, bank 0 can be mapped into 0x4000-0x7FFF.
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank as usize };
+        let offset = (bank << 14) | (addr as usize & 0x3FFF);
+        self.rom[offset & (self.rom.len() - 1)]
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | (val as u16),
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((val & 1) as u16) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = val & 0x08 != 0;
+                    self.ram_bank = val & 0x07;
+                } else {
+                    self.ram_bank = val & 0x0F;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = val;
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ROM whose banks each start with the low and high bytes of their bank number.
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = MBC5::new(rom(512), 0, false);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0);
+        mbc.write_rom(0x2000, 0x23);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x23, 0x01));
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x23, 0x00));
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        let mut mbc = MBC5::new(rom(2), 0x20000, false);
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA000), bank + 0x10);
+        }
+        // only exactly 0x0A enables RAM on MBC5.
+        mbc.write_rom(0x0000, 0x1A);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rumble_takes_ram_bank_bit_3() {
+        let mut mbc = MBC5::new(rom(2), 0x20000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x55);
+        mbc.write_rom(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x55);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
\ No newline at end of file
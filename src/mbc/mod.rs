@@ -0,0 +1,78 @@
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+pub use self::mbc1::MBC1;
+pub use self::mbc2::MBC2;
+pub use self::mbc3::{MBC3, RtcClock};
+pub use self::mbc5::MBC5;
+
+// Memory bank controllers sit between the cartridge bus and the ROM/RAM chips.
+// `addr` is always the full CPU address (0x0000-0x7FFF for ROM, 0xA000-0xBFFF for RAM).
+pub trait MBC {
+    fn read_rom(&self, addr: u16) -> u8;
+    // Writes to the ROM area never reach the ROM; they program the controller's registers.
+    fn write_rom(&mut self, addr: u16, val: u8);
+    fn read_ram(&self, addr: u16) -> u8;
+    fn write_ram(&mut self, addr: u16, val: u8);
+    // Advances any time-dependent hardware on the cartridge by `cycles` T-cycles.
+    fn tick(&mut self, _cycles: u32) {}
+    // Whether the rumble motor is currently switched on.
+    fn rumble(&self) -> bool {
+        false
+    }
+    // External RAM contents, as stored in the battery-backed part of a .sav file.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+    // Clock state to append after the RAM in a .sav file, for cartridges that have one.
+    fn rtc_footer(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+    fn load_rtc_footer(&mut self, _footer: &[u8]) {}
+}
+
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size]
+        }
+    }
+}
+
+impl MBC for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom[addr as usize]
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        let i = (addr - 0xA000) as usize;
+        if i < self.ram.len() { self.ram[i] } else { 0xFF }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        let i = (addr - 0xA000) as usize;
+        if i < self.ram.len() {
+            self.ram[i] = val;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
\ No newline at end of file
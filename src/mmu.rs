@@ -0,0 +1,798 @@
+use bus::Bus;
+use cartridge::Cartridge;
+use timer::Timer;
+use ppu::PPU;
+use apu::{APU, HighPass};
+use joypad::Joypad;
+use serial::Serial;
+use ppu::compat;
+
+// IF/IE bits, in priority order.
+pub const INT_VBLANK: u8 = 0x01;
+pub const INT_STAT: u8 = 0x02;
+pub const INT_TIMER: u8 = 0x04;
+pub const INT_SERIAL: u8 = 0x08;
+pub const INT_JOYPAD: u8 = 0x10;
+
+// The hardware being emulated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+    Dmg,
+    Mgb, // Game Boy Pocket: a DMG apart from its boot ROM and what that leaves behind
+    Cgb
+}
+
+impl Model {
+    // What a cartridge would normally be played on: CGB titles (enhanced or
+    // CGB-only) on a CGB, everything else on a DMG.
+    pub fn for_cartridge(cartridge: &Cartridge) -> Model {
+        if cartridge.header.cgb_flag & 0x80 != 0 { Model::Cgb } else { Model::Dmg }
+    }
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "cgb" => Some(Model::Cgb),
+            _ => None
+        }
+    }
+}
+
+// OAM DMA copies 160 bytes from `source` into OAM, one per M-cycle.
+struct OamDma {
+    source: u16,
+    index: u16,
+    delay: u8, // M-cycles before the first byte is copied
+    active: bool,
+    value: u8 // the byte on the source bus this cycle
+}
+
+impl OamDma {
+    // Whether the transfer is actually occupying the buses (not just starting up).
+    fn transferring(&self) -> bool {
+        self.active && self.delay == 0
+    }
+}
+
+// CGB VRAM DMA (HDMA1-5) copies 16-byte blocks into the current VRAM bank, either
+// all at once (general-purpose) or one block per HBlank.
+struct Hdma {
+    source: u16,
+    dest: u16, // offset into VRAM
+    length: u8, // blocks left, minus one, as HDMA5 reads back
+    active: bool // an HBlank transfer is in progress
+}
+
+// The DMG has two buses the CPU can contend with DMA on: the external bus
+// (cartridge and WRAM) and the video bus. HRAM and I/O are always reachable.
+fn same_bus(a: u16, b: u16) -> bool {
+    let vram = |addr: u16| (0x8000..=0x9FFF).contains(&addr);
+    vram(a) == vram(b)
+}
+
+pub struct MMU {
+    pub cartridge: Option<Cartridge>,
+    pub model: Model,
+    // mapped over the cartridge until 0xFF50 is written: 0x0000-0x00FF, plus
+    // 0x0200-0x08FF for the CGB's larger boot ROM.
+    pub boot_rom: Option<Vec<u8>>,
+    // KEY0 (0xFF4C): the CGB boot ROM sets bit 2 to drop into DMG compatibility mode.
+    key0: u8,
+    // CGB hardware running a CGB title; enables the CGB-only registers and banking.
+    pub cgb: bool,
+    // eight 4KB banks on CGB; the DMG only has the first two.
+    wram: [u8; 0x8000],
+    svbk: u8, // SVBK (0xFF70): the bank at 0xD000-0xDFFF, where 0 selects 1
+    hram: [u8; 0x7F],
+    pub timer: Timer,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub intf: u8, // IF (0xFF0F)
+    pub inte: u8, // IE (0xFFFF)
+    dma: u8,
+    oam_dma: OamDma,
+    hdma: Hdma,
+    // M-cycles the CPU is held off the bus for, after a speed switch or during VRAM DMA.
+    pub stall_cycles: u32,
+    // KEY1 (0xFF4D): the CGB speed switch is armed here and performed by STOP.
+    pub double_speed: bool,
+    pub speed_switch_armed: bool,
+    // HBlank DMA pauses while the CPU is halted.
+    cpu_halted: bool
+}
+
+impl MMU {
+    pub fn new() -> MMU {
+        MMU {
+            cartridge: None,
+            model: Model::Dmg,
+            boot_rom: None,
+            key0: 0,
+            cgb: false,
+            wram: [0; 0x8000],
+            svbk: 0,
+            hram: [0; 0x7F],
+            timer: Timer::new(),
+            ppu: PPU::new(),
+            apu: APU::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            intf: 0,
+            inte: 0,
+            dma: 0xFF,
+            oam_dma: OamDma {
+                source: 0,
+                index: 0,
+                delay: 0,
+                active: false,
+                value: 0xFF
+            },
+            hdma: Hdma {
+                source: 0,
+                dest: 0,
+                length: 0x7F,
+                active: false
+            },
+            stall_cycles: 0,
+            double_speed: false,
+            speed_switch_armed: false,
+            cpu_halted: false
+        }
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        let model = Model::for_cartridge(&cartridge);
+        self.load_cartridge_with_model(cartridge, model);
+    }
+
+    // On a CGB, titles flagged as CGB-enhanced (0x80) or CGB-only (0xC0) run in CGB
+    // mode and the rest in DMG compatibility mode, coloured by the palettes the boot
+    // ROM would choose for them. A CGB boot ROM, if there is one, starts out in CGB
+    // mode and makes that choice itself.
+    pub fn load_cartridge_with_model(&mut self, cartridge: Cartridge, model: Model) {
+        self.model = model;
+        let cgb_title = cartridge.header.cgb_flag & 0x80 != 0;
+        self.cgb = model == Model::Cgb && (cgb_title || self.boot_rom.is_some());
+        self.ppu.video.cgb = self.cgb;
+        self.ppu.video.compat = model == Model::Cgb && !self.cgb;
+        self.apu.set_high_pass(if model == Model::Cgb { HighPass::Cgb } else { HighPass::Dmg });
+        if self.ppu.video.compat {
+            compat::load_combination(&mut self.ppu.video, compat::title_combination(&cartridge));
+        }
+        self.cartridge = Some(cartridge);
+    }
+
+    // T-cycles (PPU dots) per CPU M-cycle; double speed halves the CPU's share.
+    pub fn dots_per_cycle(&self) -> u32 {
+        if self.double_speed { 2 } else { 4 }
+    }
+
+    // Sets the I/O registers to what the boot ROM leaves behind when it hands
+    // over to the cartridge.
+    pub fn set_post_boot_state(&mut self) {
+        self.intf = INT_VBLANK;
+        // DIV depends on how long the CGB boot ROM spent on the logo, so is left alone there.
+        if self.model != Model::Cgb {
+            self.timer.set_counter(0xABCC);
+        }
+        if self.ppu.video.compat {
+            self.key0 = 0x04;
+        }
+        self.joypad.write_byte(0x00);
+        if self.model == Model::Cgb {
+            // SC bit 0 (internal clock) is left set on CGB, with no transfer running.
+            self.serial.write_byte(0xFF02, 0x01);
+            self.dma = 0x00;
+        }
+        // the second note of the boot sound is still ringing out on channel 1.
+        self.apu.write_byte(0xFF26, 0x80);
+        self.apu.write_byte(0xFF11, 0x80);
+        self.apu.write_byte(0xFF12, 0xF3);
+        self.apu.write_byte(0xFF13, 0xC1);
+        self.apu.write_byte(0xFF14, 0x87);
+        self.apu.write_byte(0xFF24, 0x77);
+        self.apu.write_byte(0xFF25, 0xF3);
+        self.ppu.write_byte(0xFF47, 0xFC);
+        self.ppu.write_byte(0xFF48, 0xFF);
+        self.ppu.write_byte(0xFF49, 0xFF);
+        self.ppu.set_post_boot_state();
+    }
+
+    // Writing BOOT (0xFF50) unmaps the boot ROM for good. By then a CGB boot ROM
+    // has picked CGB or DMG compatibility mode and set up the palettes to suit.
+    fn unmap_boot_rom(&mut self) {
+        self.boot_rom = None;
+        if self.model == Model::Cgb && self.key0 & 0x04 != 0 {
+            self.cgb = false;
+            self.ppu.video.cgb = false;
+            self.ppu.video.compat = true;
+        }
+    }
+
+    fn boot_rom_byte(&self, addr: u16) -> Option<u8> {
+        match self.boot_rom {
+            // the cartridge header shows through the gap in a CGB boot ROM.
+            Some(ref rom) if !(0x0100..0x0200).contains(&addr) && (addr as usize) < rom.len() => Some(rom[addr as usize]),
+            _ => None
+        }
+    }
+
+    // Offset into `wram` of an address in 0xC000-0xDFFF or its echo.
+    fn wram_index(&self, addr: u16) -> usize {
+        let offset = (addr & 0x0FFF) as usize;
+        if addr & 0x1000 == 0 {
+            return offset;
+        }
+        let bank = if self.cgb { (self.svbk & 0x07).max(1) } else { 1 };
+        bank as usize * 0x1000 + offset
+    }
+
+    // Whether a CPU access to `addr` collides with a running OAM DMA.
+    fn dma_conflict(&self, addr: u16) -> bool {
+        if !self.oam_dma.transferring() || addr >= 0xFF00 {
+            return false;
+        }
+        (0xFE00..=0xFEFF).contains(&addr) || same_bus(addr, self.oam_dma.source)
+    }
+
+    pub fn read_byte(&mut self, addr: u16) -> u8 {
+        if self.dma_conflict(addr) {
+            // OAM is busy being written; anything else on the DMA's bus sees the byte in flight.
+            return if addr >= 0xFE00 { 0xFF } else { self.oam_dma.value };
+        }
+        self.read_mapped(addr)
+    }
+
+    // Reads memory as it is, without OAM DMA bus conflicts or the PPU's VRAM/OAM
+    // locks, so debugging tools can watch it without changing what they see.
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.ppu.peek_vram(addr),
+            0xFE00..=0xFE9F => self.ppu.video.oam[(addr - 0xFE00) as usize],
+            _ => self.read_mapped(addr)
+        }
+    }
+
+    fn read_mapped(&self, addr: u16) -> u8 {
+        if let Some(val) = self.boot_rom_byte(addr) {
+            return val;
+        }
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => match self.cartridge {
+                Some(ref cart) => cart.read_byte(addr),
+                None => 0xFF
+            },
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)], // 0xE000 up echoes 0xC000-0xDDFF
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
+            // unusable; on DMG it reads 0, or 0xFF while the PPU has OAM locked.
+            0xFEA0..=0xFEFF => if self.ppu.oam_accessible() { 0x00 } else { 0xFF },
+            0xFF00..=0xFF7F => self.read_io(addr),
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
+            _ => self.inte
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        if self.dma_conflict(addr) {
+            return;
+        }
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => if let Some(ref mut cart) = self.cartridge {
+                cart.write_byte(addr, val);
+            },
+            0x8000..=0x9FFF => self.ppu.write_vram(addr, val),
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)] = val,
+            0xFE00..=0xFE9F => self.ppu.write_oam(addr, val),
+            0xFEA0..=0xFEFF => {},
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = val,
+            _ => self.inte = val
+        }
+    }
+
+    // Unmapped I/O addresses, and unused bits of mapped ones, read back as 1.
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => self.joypad.read_byte(),
+            0xFF01..=0xFF02 => self.serial.read_byte(addr),
+            0xFF04..=0xFF07 => self.timer.read_byte(addr),
+            0xFF0F => self.intf | 0xE0,
+            0xFF10..=0xFF3F => self.apu.read_byte(addr),
+            0xFF46 => self.dma,
+            0xFF40..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => self.ppu.read_byte(addr),
+            0xFF4D if self.cgb => ((self.double_speed as u8) << 7) | 0x7E | (self.speed_switch_armed as u8),
+            0xFF55 if self.cgb => ((!self.hdma.active as u8) << 7) | self.hdma.length,
+            0xFF70 if self.cgb => 0xF8 | self.svbk,
+            _ => 0xFF
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => self.joypad.write_byte(val),
+            0xFF01..=0xFF02 => self.serial.write_byte(addr, val),
+            0xFF04..=0xFF07 => self.timer.write_byte(addr, val),
+            0xFF0F => self.intf = val & 0x1F,
+            0xFF10..=0xFF3F => self.apu.write_byte(addr, val),
+            0xFF46 => {
+                self.dma = val;
+                // sources above 0xDFFF land in WRAM, just as the echo area does.
+                let source = (val as u16) << 8;
+                self.oam_dma.source = if source >= 0xE000 { source - 0x2000 } else { source };
+                self.oam_dma.index = 0;
+                self.oam_dma.delay = 1;
+                self.oam_dma.active = true;
+            },
+            0xFF40..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => self.ppu.write_byte(addr, val),
+            0xFF4C if self.cgb && self.boot_rom.is_some() => self.key0 = val,
+            0xFF4D if self.cgb => self.speed_switch_armed = val & 0x01 != 0,
+            0xFF50 if self.boot_rom.is_some() && val & 0x01 != 0 => self.unmap_boot_rom(),
+            0xFF51 if self.cgb => self.hdma.source = (self.hdma.source & 0x00FF) | ((val as u16) << 8),
+            0xFF52 if self.cgb => self.hdma.source = (self.hdma.source & 0xFF00) | (val & 0xF0) as u16,
+            0xFF53 if self.cgb => self.hdma.dest = (self.hdma.dest & 0x00FF) | (((val & 0x1F) as u16) << 8),
+            0xFF54 if self.cgb => self.hdma.dest = (self.hdma.dest & 0xFF00) | (val & 0xF0) as u16,
+            0xFF55 if self.cgb => self.start_hdma(val),
+            0xFF70 if self.cgb => self.svbk = val & 0x07,
+            _ => {}
+        }
+    }
+
+    // Advances the hardware outside the CPU by `cycles` M-cycles. The timer, serial
+    // port and OAM DMA run off the CPU clock; everything else sees real time.
+    pub fn tick(&mut self, cycles: u32) {
+        let dots = cycles * self.dots_per_cycle();
+        if let Some(ref mut cart) = self.cartridge {
+            cart.tick(dots);
+        }
+        for _ in 0..cycles {
+            self.tick_oam_dma();
+        }
+        self.timer.tick(cycles);
+        self.serial.tick(cycles);
+        self.ppu.tick(dots);
+        if self.ppu.hblank_started {
+            self.ppu.hblank_started = false;
+            if self.hdma.active && !self.cpu_halted {
+                self.hdma_block();
+            }
+        }
+        self.apu.tick(dots);
+        self.intf |= self.timer.interrupt | self.serial.interrupt | self.ppu.interrupt | self.joypad.interrupt;
+        self.timer.interrupt = 0;
+        self.serial.interrupt = 0;
+        self.ppu.interrupt = 0;
+        self.joypad.interrupt = 0;
+    }
+
+    // HDMA5: bit 7 picks an HBlank transfer over a general-purpose one, and the
+    // rest is the length in blocks, minus one.
+    fn start_hdma(&mut self, val: u8) {
+        if self.hdma.active && val & 0x80 == 0 {
+            // stops the HBlank transfer; the remaining length stays readable.
+            self.hdma.active = false;
+            return;
+        }
+        self.hdma.length = val & 0x7F;
+        if val & 0x80 != 0 {
+            self.hdma.active = true;
+            // started during HBlank, or with the LCD off, the first block goes straight away.
+            if self.ppu.in_hblank() {
+                self.hdma_block();
+            }
+        } else {
+            let blocks = self.hdma.length as u32 + 1;
+            for _ in 0..blocks {
+                self.hdma_block();
+            }
+        }
+    }
+
+    // Copies one 16-byte block, which takes 32 dots whatever the CPU speed.
+    fn hdma_block(&mut self) {
+        for _ in 0..0x10 {
+            let val = self.read_mapped(self.hdma.source);
+            self.ppu.write_vram(0x8000 | self.hdma.dest, val);
+            self.hdma.source = self.hdma.source.wrapping_add(1);
+            self.hdma.dest = (self.hdma.dest + 1) & 0x1FFF;
+        }
+        self.stall_cycles += 32 / self.dots_per_cycle();
+        if self.hdma.length == 0 {
+            self.hdma.length = 0x7F;
+            self.hdma.active = false;
+        } else {
+            self.hdma.length -= 1;
+        }
+    }
+
+    fn tick_oam_dma(&mut self) {
+        if !self.oam_dma.active {
+            return;
+        }
+        if self.oam_dma.delay > 0 {
+            self.oam_dma.delay -= 1;
+            return;
+        }
+        let i = self.oam_dma.index;
+        let val = self.read_mapped(self.oam_dma.source + i);
+        self.oam_dma.value = val;
+        self.ppu.video.oam[i as usize] = val;
+        self.oam_dma.index += 1;
+        if self.oam_dma.index == 0xA0 {
+            self.oam_dma.active = false;
+        }
+    }
+
+    pub fn read_word(&mut self, addr: u16) -> u16 {
+        (self.read_byte(addr) as u16) | ((self.read_byte(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    pub fn write_word(&mut self, addr: u16, val: u16) {
+        self.write_byte(addr, (val & 0x00FF) as u8);
+        self.write_byte(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+}
+
+impl Bus for MMU {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        MMU::read_byte(self, addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        MMU::write_byte(self, addr, val);
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        MMU::tick(self, cycles);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.inte & self.intf & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.intf &= !(1 << bit);
+    }
+
+    fn stall(&mut self) -> bool {
+        if self.stall_cycles == 0 {
+            return false;
+        }
+        self.stall_cycles -= 1;
+        true
+    }
+
+    fn set_halted(&mut self, halted: bool) {
+        self.cpu_halted = halted;
+    }
+
+    fn joypad_active(&self) -> bool {
+        self.joypad.read_byte() & 0x0F != 0x0F
+    }
+
+    fn stop(&mut self) -> bool {
+        let switch = self.speed_switch_armed;
+        if switch {
+            // the CPU sits idle while the clock settles.
+            self.speed_switch_armed = false;
+            self.double_speed = !self.double_speed;
+            self.stall_cycles = 2050;
+        }
+        self.timer.write_byte(0xFF04, 0);
+        switch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::header_checksum;
+
+    fn cartridge(cgb_flag: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = cgb_flag;
+        rom[0x14D] = header_checksum(&rom);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    // I/O registers after the boot ROM, on DMG, MGB and CGB (running a CGB title),
+    // from the Pan Docs table. None marks values that vary from unit to unit.
+    const POST_BOOT_IO: [(u16, [Option<u8>; 3]); 47] = [
+        (0xFF00, [Some(0xCF), Some(0xCF), Some(0xCF)]), // P1
+        (0xFF01, [Some(0x00), Some(0x00), Some(0x00)]), // SB
+        (0xFF02, [Some(0x7E), Some(0x7E), Some(0x7F)]), // SC
+        (0xFF04, [Some(0xAB), Some(0xAB), None]),       // DIV
+        (0xFF05, [Some(0x00), Some(0x00), Some(0x00)]), // TIMA
+        (0xFF06, [Some(0x00), Some(0x00), Some(0x00)]), // TMA
+        (0xFF07, [Some(0xF8), Some(0xF8), Some(0xF8)]), // TAC
+        (0xFF0F, [Some(0xE1), Some(0xE1), Some(0xE1)]), // IF
+        (0xFF10, [Some(0x80), Some(0x80), Some(0x80)]), // NR10
+        (0xFF11, [Some(0xBF), Some(0xBF), Some(0xBF)]), // NR11
+        (0xFF12, [Some(0xF3), Some(0xF3), Some(0xF3)]), // NR12
+        (0xFF13, [Some(0xFF), Some(0xFF), Some(0xFF)]), // NR13
+        (0xFF14, [Some(0xBF), Some(0xBF), Some(0xBF)]), // NR14
+        (0xFF16, [Some(0x3F), Some(0x3F), Some(0x3F)]), // NR21
+        (0xFF17, [Some(0x00), Some(0x00), Some(0x00)]), // NR22
+        (0xFF18, [Some(0xFF), Some(0xFF), Some(0xFF)]), // NR23
+        (0xFF19, [Some(0xBF), Some(0xBF), Some(0xBF)]), // NR24
+        (0xFF1A, [Some(0x7F), Some(0x7F), Some(0x7F)]), // NR30
+        (0xFF1B, [Some(0xFF), Some(0xFF), Some(0xFF)]), // NR31
+        (0xFF1C, [Some(0x9F), Some(0x9F), Some(0x9F)]), // NR32
+        (0xFF1D, [Some(0xFF), Some(0xFF), Some(0xFF)]), // NR33
+        (0xFF1E, [Some(0xBF), Some(0xBF), Some(0xBF)]), // NR34
+        (0xFF20, [Some(0xFF), Some(0xFF), Some(0xFF)]), // NR41
+        (0xFF21, [Some(0x00), Some(0x00), Some(0x00)]), // NR42
+        (0xFF22, [Some(0x00), Some(0x00), Some(0x00)]), // NR43
+        (0xFF23, [Some(0xBF), Some(0xBF), Some(0xBF)]), // NR44
+        (0xFF24, [Some(0x77), Some(0x77), Some(0x77)]), // NR50
+        (0xFF25, [Some(0xF3), Some(0xF3), Some(0xF3)]), // NR51
+        (0xFF26, [Some(0xF1), Some(0xF1), Some(0xF1)]), // NR52
+        (0xFF40, [Some(0x91), Some(0x91), Some(0x91)]), // LCDC
+        (0xFF41, [Some(0x85), Some(0x85), Some(0x85)]), // STAT
+        (0xFF42, [Some(0x00), Some(0x00), Some(0x00)]), // SCY
+        (0xFF43, [Some(0x00), Some(0x00), Some(0x00)]), // SCX
+        (0xFF44, [Some(0x00), Some(0x00), Some(0x00)]), // LY
+        (0xFF45, [Some(0x00), Some(0x00), Some(0x00)]), // LYC
+        (0xFF46, [Some(0xFF), Some(0xFF), Some(0x00)]), // DMA
+        (0xFF47, [Some(0xFC), Some(0xFC), Some(0xFC)]), // BGP
+        (0xFF48, [Some(0xFF), Some(0xFF), Some(0xFF)]), // OBP0
+        (0xFF49, [Some(0xFF), Some(0xFF), Some(0xFF)]), // OBP1
+        (0xFF4A, [Some(0x00), Some(0x00), Some(0x00)]), // WY
+        (0xFF4B, [Some(0x00), Some(0x00), Some(0x00)]), // WX
+        (0xFF4D, [Some(0xFF), Some(0xFF), Some(0x7E)]), // KEY1
+        (0xFF4F, [Some(0xFF), Some(0xFF), Some(0xFE)]), // VBK
+        (0xFF51, [Some(0xFF), Some(0xFF), Some(0xFF)]), // HDMA1
+        (0xFF55, [Some(0xFF), Some(0xFF), Some(0xFF)]), // HDMA5
+        (0xFF70, [Some(0xFF), Some(0xFF), Some(0xF8)]), // SVBK
+        (0xFFFF, [Some(0x00), Some(0x00), Some(0x00)])  // IE
+    ];
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xC123, 0x45);
+        assert_eq!(mmu.read_byte(0xE123), 0x45);
+        mmu.write_byte(0xFDFF, 0x67);
+        assert_eq!(mmu.read_byte(0xDDFF), 0x67);
+    }
+
+    #[test]
+    fn unusable_area() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xFEA0, 0x12);
+        assert_eq!(mmu.read_byte(0xFEA0), 0x00);
+        // OAM is locked during mode 2, which locks this area too.
+        mmu.write_byte(0xFF40, 0x80);
+        assert_eq!(mmu.read_byte(0xFEFF), 0xFF);
+    }
+
+    #[test]
+    fn hram_ie_and_unmapped_io() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xFF80, 0x11);
+        mmu.write_byte(0xFFFE, 0x22);
+        mmu.write_byte(0xFFFF, 0x1F);
+        assert_eq!((mmu.read_byte(0xFF80), mmu.read_byte(0xFFFE), mmu.read_byte(0xFFFF)), (0x11, 0x22, 0x1F));
+        mmu.write_byte(0xFF0F, 0xFF);
+        assert_eq!(mmu.read_byte(0xFF0F), 0xFF);
+        assert_eq!(mmu.intf, 0x1F);
+        mmu.write_byte(0xFF03, 0x00);
+        assert_eq!(mmu.read_byte(0xFF03), 0xFF);
+        // a DMG has no KEY1 or SVBK.
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF70), 0xFF);
+    }
+
+    fn cgb() -> MMU {
+        let mut mmu = MMU::new();
+        mmu.load_cartridge_with_model(cartridge(0x80), Model::Cgb);
+        mmu
+    }
+
+    #[test]
+    fn high_pass_follows_model() {
+        for &(model, filter) in [(Model::Dmg, HighPass::Dmg), (Model::Mgb, HighPass::Dmg), (Model::Cgb, HighPass::Cgb)].iter() {
+            let mut mmu = MMU::new();
+            // a DMG game on a CGB still goes through the CGB's capacitor.
+            mmu.load_cartridge_with_model(cartridge(0x00), model);
+            assert_eq!(mmu.apu.high_pass(), filter);
+        }
+    }
+
+    #[test]
+    fn svbk_zero_selects_bank_1() {
+        let mut mmu = cgb();
+        mmu.write_byte(0xFF70, 0x01);
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(0xFF70, 0x00);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+        assert_eq!(mmu.read_byte(0xFF70), 0xF8);
+    }
+
+    #[test]
+    fn wram_banks_are_separate() {
+        let mut mmu = cgb();
+        mmu.write_byte(0xC000, 0xC0);
+        for bank in 1..8 {
+            mmu.write_byte(0xFF70, bank);
+            mmu.write_byte(0xD000, bank * 0x10);
+            mmu.write_byte(0xDFFF, bank);
+        }
+        for bank in 1..8 {
+            mmu.write_byte(0xFF70, bank | 0xF8);
+            assert_eq!(mmu.read_byte(0xFF70), 0xF8 | bank);
+            assert_eq!(mmu.read_byte(0xD000), bank * 0x10);
+            assert_eq!(mmu.read_byte(0xDFFF), bank);
+            // bank 0 stays put, and echo RAM follows the selected bank.
+            assert_eq!(mmu.read_byte(0xC000), 0xC0);
+            assert_eq!(mmu.read_byte(0xF000), bank * 0x10);
+        }
+    }
+
+    #[test]
+    fn dmg_mode_ignores_svbk() {
+        let mut mmu = MMU::new();
+        mmu.load_cartridge_with_model(cartridge(0x00), Model::Cgb);
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(0xFF70, 0x02);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+        assert_eq!(mmu.read_byte(0xFF70), 0xFF);
+    }
+
+    #[test]
+    fn key1_speed_switch() {
+        let mut mmu = cgb();
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7E);
+        mmu.write_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7F);
+        assert_eq!(mmu.dots_per_cycle(), 4);
+        assert!(mmu.stop());
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFE);
+        assert_eq!(mmu.dots_per_cycle(), 2);
+        // STOP without arming doesn't switch back.
+        assert!(!mmu.stop());
+        assert_eq!(mmu.dots_per_cycle(), 2);
+        mmu.write_byte(0xFF4D, 0x01);
+        assert!(mmu.stop());
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7E);
+    }
+
+    #[test]
+    fn double_speed_halves_ppu_dots_per_cycle() {
+        let mut mmu = cgb();
+        mmu.write_byte(0xFF4D, 0x01);
+        mmu.stop();
+        mmu.write_byte(0xFF40, 0x91);
+        // mode 3 starts 80 dots in, which is 40 M-cycles at double speed.
+        mmu.tick(39);
+        assert_eq!(mmu.read_byte(0xFF41) & 0x03, 2);
+        mmu.tick(1);
+        assert_eq!(mmu.read_byte(0xFF41) & 0x03, 3);
+    }
+
+    // A DMG MMU with a 0xA0-byte pattern at `source`, and an OAM DMA from there just started.
+    fn oam_dma_from(source: u16) -> MMU {
+        let mut mmu = MMU::new();
+        for i in 0..0xA0 {
+            mmu.write_byte(source + i, i as u8 + 0x10);
+        }
+        mmu.write_byte(0xFF46, (source >> 8) as u8);
+        mmu
+    }
+
+    #[test]
+    fn oam_dma_copies_160_bytes() {
+        let mut mmu = oam_dma_from(0xC000);
+        mmu.tick(160);
+        assert_eq!(mmu.read_byte(0xFE9F), 0xFF);
+        mmu.tick(1);
+        for i in 0..0xA0 {
+            assert_eq!(mmu.read_byte(0xFE00 + i), i as u8 + 0x10);
+        }
+        assert_eq!(mmu.read_byte(0xFF46), 0xC0);
+    }
+
+    #[test]
+    fn oam_dma_from_echo_ram_reads_wram() {
+        let mut mmu = oam_dma_from(0xC000);
+        mmu.tick(161);
+        mmu.write_byte(0xFE00, 0);
+        mmu.write_byte(0xFF46, 0xE0);
+        mmu.tick(161);
+        assert_eq!(mmu.read_byte(0xFE00), 0x10);
+    }
+
+    #[test]
+    fn oam_dma_bus_conflicts() {
+        let mut mmu = oam_dma_from(0xC000);
+        mmu.write_byte(0x8000, 0x55);
+        mmu.tick(2);
+        // the external bus returns the byte being copied, and ignores writes.
+        assert_eq!(mmu.read_byte(0xD123), 0x10);
+        mmu.write_byte(0xC100, 0x99);
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+        // HRAM, I/O and the other bus are still reachable.
+        mmu.write_byte(0xFF80, 0x42);
+        assert_eq!(mmu.read_byte(0xFF80), 0x42);
+        assert_eq!(mmu.read_byte(0xFF46), 0xC0);
+        assert_eq!(mmu.read_byte(0x8000), 0x55);
+        mmu.tick(159);
+        assert_eq!(mmu.read_byte(0xC100), 0x00);
+        mmu.write_byte(0xC100, 0x99);
+        assert_eq!(mmu.read_byte(0xC100), 0x99);
+    }
+
+    #[test]
+    fn oam_dma_from_vram_leaves_wram_reachable() {
+        let mut mmu = oam_dma_from(0x8000);
+        mmu.write_byte(0xC000, 0x77);
+        mmu.tick(2);
+        assert_eq!(mmu.read_byte(0xC000), 0x77);
+        assert_eq!(mmu.read_byte(0x9000), 0x10);
+    }
+
+    // A CGB MMU with 0x20 bytes of pattern in WRAM, set up to copy them to 0x8000.
+    fn hdma_setup() -> MMU {
+        let mut mmu = MMU::new();
+        mmu.load_cartridge_with_model(cartridge(0x80), Model::Cgb);
+        for i in 0..0x20 {
+            mmu.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        for &(addr, val) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x00), (0xFF54, 0x00)].iter() {
+            mmu.write_byte(addr, val);
+        }
+        mmu
+    }
+
+    #[test]
+    fn hdma_lcd_off_copies_first_block() {
+        let mut mmu = hdma_setup();
+        mmu.write_byte(0xFF55, 0x81);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+        assert_eq!(mmu.read_byte(0x800F), 0x10);
+        assert_eq!(mmu.read_byte(0x8010), 0x00);
+    }
+
+    #[test]
+    fn hdma_in_hblank_copies_first_block() {
+        let mut mmu = hdma_setup();
+        mmu.write_byte(0xFF40, 0x91);
+        while !mmu.ppu.in_hblank() {
+            mmu.tick(1);
+        }
+        mmu.write_byte(0xFF55, 0x81);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+        assert_eq!(mmu.read_byte(0x800F), 0x10);
+    }
+
+    #[test]
+    fn hdma_pauses_while_halted() {
+        let mut mmu = hdma_setup();
+        mmu.write_byte(0xFF40, 0x91);
+        mmu.write_byte(0xFF55, 0x81);
+        assert_eq!(mmu.read_byte(0xFF55), 0x01);
+        mmu.set_halted(true);
+        mmu.tick(114);
+        assert_eq!(mmu.read_byte(0xFF55), 0x01);
+        mmu.set_halted(false);
+        mmu.tick(114);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+    }
+
+    #[test]
+    fn post_boot_io() {
+        for (i, &model) in [Model::Dmg, Model::Mgb, Model::Cgb].iter().enumerate() {
+            let mut mmu = MMU::new();
+            mmu.load_cartridge_with_model(cartridge(0x80), model);
+            mmu.set_post_boot_state();
+            for &(addr, values) in POST_BOOT_IO.iter() {
+                if let Some(expected) = values[i] {
+                    assert_eq!(mmu.read_byte(addr), expected, "{:?} {:04x}", model, addr);
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
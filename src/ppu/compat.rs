@@ -0,0 +1,232 @@
+use ppu::VideoState;
+use cartridge::Cartridge;
+
+// What the CGB boot ROM does for DMG cartridges: it hashes the title to pick
+// BG, OBJ0 and OBJ1 palettes from a table of known Nintendo games, or lets the
+// player override that by holding a button combination during the logo.
+
+// RGB555 colours, four per palette.
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000]
+];
+
+// OBJ0, OBJ1 and BG palettes, as offsets in colours into PALETTES. A few don't
+// start on a palette boundary, and so borrow the last colour of the palette before.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116], [72, 72, 72], [80, 80, 80], [96, 96, 96], [36, 36, 36],
+    [0, 0, 0], [108, 108, 108], [20, 20, 20], [48, 48, 48], [104, 104, 104],
+    [64, 32, 32], [16, 112, 112], [16, 8, 8], [12, 16, 16], [16, 116, 116],
+    [112, 16, 112], [8, 68, 8], [64, 64, 32], [16, 16, 28], [16, 16, 72],
+    [16, 16, 80], [76, 76, 36], [15, 15, 44], [68, 68, 8], [16, 16, 8],
+    [16, 16, 12], [112, 112, 0], [12, 12, 0], [0, 0, 4], [72, 88, 72],
+    [80, 88, 80], [96, 88, 96], [64, 88, 32], [68, 16, 52], [111, 0, 56],
+    [111, 16, 60], [76, 88, 36], [64, 112, 40], [16, 92, 112], [68, 88, 8],
+    [16, 0, 8], [16, 112, 12], [112, 12, 0], [12, 112, 16], [84, 112, 16],
+    [12, 112, 0], [100, 12, 112], [0, 112, 32], [16, 12, 112], [112, 12, 24],
+    [16, 112, 116]
+];
+
+// Sums of the 16 title bytes of the games the boot ROM knows. The last 14 are
+// shared by more than one title, which the fourth letter tells apart.
+const CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4
+];
+const UNIQUE_CHECKSUMS: usize = 65;
+
+// Fourth title letters for the shared checksums, in rows of 14 that line up
+// with the end of CHECKSUMS.
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// The combination for each checksum, then for each fourth letter.
+const CHECKSUM_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 14, 16, 25, 42, 42, 5, 0,
+    39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29
+];
+
+// The combinations held on the joypad at boot to override the choice.
+pub const KEY_COMBINATIONS: [(&str, usize); 12] = [
+    ("right", 1), ("left", 48), ("up", 5), ("down", 8),
+    ("right+a", 0), ("left+a", 40), ("up+a", 43), ("down+a", 3),
+    ("right+b", 6), ("left+b", 7), ("up+b", 28), ("down+b", 49)
+];
+
+// The sum of the title bytes, which the boot ROM only works out for Nintendo's
+// own titles.
+pub fn title_checksum(cartridge: &Cartridge) -> Option<u8> {
+    let old_licensee = cartridge.read_byte(0x014B);
+    let nintendo = old_licensee == 0x01 ||
+        (old_licensee == 0x33 && cartridge.read_byte(0x0144) == b'0' && cartridge.read_byte(0x0145) == b'1');
+    if !nintendo {
+        return None;
+    }
+    Some((0x0134..0x0144).fold(0u8, |sum, addr| sum.wrapping_add(cartridge.read_byte(addr))))
+}
+
+// The combination the boot ROM would pick for `cartridge`. Titles it doesn't
+// know get the default.
+pub fn title_combination(cartridge: &Cartridge) -> usize {
+    let checksum = match title_checksum(cartridge) {
+        Some(checksum) => checksum,
+        None => return 0
+    };
+    let fourth = cartridge.read_byte(0x0137);
+    for (i, &c) in CHECKSUMS.iter().enumerate() {
+        if c != checksum {
+            continue;
+        }
+        if i < UNIQUE_CHECKSUMS {
+            return CHECKSUM_COMBINATIONS[i] as usize;
+        }
+        let mut j = i - UNIQUE_CHECKSUMS;
+        while j < FOURTH_LETTERS.len() {
+            if FOURTH_LETTERS[j] == fourth {
+                return CHECKSUM_COMBINATIONS[UNIQUE_CHECKSUMS + j] as usize;
+            }
+            j += CHECKSUMS.len() - UNIQUE_CHECKSUMS;
+        }
+    }
+    0
+}
+
+// Looks up a button combination by name, e.g. "left+a".
+pub fn key_combination(name: &str) -> Option<usize> {
+    KEY_COMBINATIONS.iter().find(|&&(key, _)| key == name).map(|&(_, combination)| combination)
+}
+
+// Loads a combination into palette RAM: BG palette 0, and OBJ palettes 0 and 1.
+pub fn load_combination(video: &mut VideoState, combination: usize) {
+    let [obj0, obj1, bg] = COMBINATIONS[combination];
+    write_palette(&mut video.obj_palettes[0..8], obj0);
+    write_palette(&mut video.obj_palettes[8..16], obj1);
+    write_palette(&mut video.bg_palettes[0..8], bg);
+}
+
+fn write_palette(ram: &mut [u8], offset: usize) {
+    for i in 0..4 {
+        let color = PALETTES[(offset + i) / 4][(offset + i) % 4];
+        ram[i * 2] = color as u8;
+        ram[i * 2 + 1] = (color >> 8) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::header_checksum;
+    use ppu::PPU;
+
+    fn cartridge(title: &[u8], old_licensee: u8, new_licensee: &[u8; 2]) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x144..0x146].copy_from_slice(new_licensee);
+        rom[0x14B] = old_licensee;
+        rom[0x14D] = header_checksum(&rom);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    fn nintendo(title: &[u8]) -> Cartridge {
+        cartridge(title, 0x01, b"00")
+    }
+
+    // A title whose bytes sum to `checksum`, with `fourth` as its fourth letter.
+    fn title_with(checksum: u8, fourth: u8) -> Cartridge {
+        let mut title = [0; 4];
+        title[0] = checksum.wrapping_sub(fourth);
+        title[3] = fourth;
+        nintendo(&title)
+    }
+
+    fn colors(ram: &[u8]) -> Vec<u16> {
+        ram.chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect()
+    }
+
+    #[test]
+    fn checksum_only_for_nintendo_titles() {
+        assert_eq!(title_checksum(&nintendo(b"TETRIS")), Some(0xDB));
+        assert_eq!(title_checksum(&cartridge(b"TETRIS", 0x33, b"01")), Some(0xDB));
+        assert_eq!(title_checksum(&cartridge(b"TETRIS", 0x33, b"08")), None);
+        assert_eq!(title_checksum(&cartridge(b"TETRIS", 0x08, b"01")), None);
+    }
+
+    #[test]
+    fn unique_checksum_picks_combination() {
+        assert_eq!(title_combination(&nintendo(b"TETRIS")), 3);
+        // the fourth letter doesn't matter for a checksum only one game has.
+        assert_eq!(title_combination(&title_with(0xDB, b'Z')), 3);
+    }
+
+    #[test]
+    fn fourth_letter_disambiguates_shared_checksums() {
+        assert_eq!(title_combination(&title_with(0x46, b'E')), 22);
+        assert_eq!(title_combination(&title_with(0x46, b'R')), 46);
+        assert_eq!(title_combination(&title_with(0xB3, b'B')), 36);
+        assert_eq!(title_combination(&title_with(0xB3, b'U')), 17);
+        assert_eq!(title_combination(&title_with(0xB3, b'R')), 29);
+    }
+
+    #[test]
+    fn unknown_titles_get_default() {
+        assert_eq!(title_combination(&title_with(0x46, b'Q')), 0);
+        assert_eq!(title_combination(&title_with(0x02, b'A')), 0);
+        assert_eq!(title_combination(&cartridge(b"TETRIS", 0x08, b"00")), 0);
+    }
+
+    #[test]
+    fn key_combinations_by_name() {
+        assert_eq!(key_combination("left+a"), Some(40));
+        assert_eq!(key_combination("down+b"), Some(49));
+        assert_eq!(key_combination("a"), None);
+    }
+
+    #[test]
+    fn load_combination_fills_palette_ram() {
+        let mut ppu = PPU::new();
+        load_combination(&mut ppu.video, 0);
+        assert_eq!(colors(&ppu.video.obj_palettes[0..8]), [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(colors(&ppu.video.obj_palettes[8..16]), [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(colors(&ppu.video.bg_palettes[0..8]), [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+        // combination 22 starts one colour before a palette boundary.
+        load_combination(&mut ppu.video, 22);
+        assert_eq!(colors(&ppu.video.obj_palettes[0..8]), [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(colors(&ppu.video.bg_palettes[0..8]), [0x7ED6, 0x4BFF, 0x2175, 0x0000]);
+        // the other palettes are left alone.
+        assert_eq!(ppu.video.bg_palettes[8], 0xFF);
+    }
+}
\ No newline at end of file
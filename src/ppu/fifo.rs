@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use ppu::{Renderer, VideoState, bitplane_pixel};
+
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    attr: u8,
+    priority: u16 // of the sprite it came from; see VideoState::sprite_priority
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel { color: 0, attr: 0, priority: 0 };
+
+// Models the hardware pixel pipeline: a background fetcher feeding a pixel FIFO
+// that shifts out one pixel per dot, with sprite fetches stalling it. Registers
+// are sampled as the fetcher reaches them, so mid-line writes land where they
+// would on hardware, and mode 3 ends up exactly as long as the pipeline needs.
+pub struct FifoRenderer {
+    bg_fifo: VecDeque<(u8, u8)>, // colour number and map attributes
+    obj_fifo: VecDeque<ObjPixel>,
+    fetch_step: u8, // dots spent on the current tile; 6 means waiting for room to push
+    fetch_x: u8,    // tile column being fetched, relative to the BG scroll or window start
+    fetch_tile: u8,
+    fetch_attr: u8,
+    fetch_lo: u8,
+    fetch_hi: u8,
+    first_fetch: bool, // the first tile fetched on each line is thrown away
+    in_window: bool,
+    window_drawn: bool,
+    discard: u8, // pixels still to drop for SCX fine scrolling
+    lx: u8,      // next screen column to output
+    next_sprite: usize,
+    sprite_dots: u8 // dots left on the sprite fetch in progress
+}
+
+impl FifoRenderer {
+    pub fn new() -> FifoRenderer {
+        FifoRenderer {
+            bg_fifo: VecDeque::with_capacity(8),
+            obj_fifo: VecDeque::with_capacity(8),
+            fetch_step: 0,
+            fetch_x: 0,
+            fetch_tile: 0,
+            fetch_attr: 0,
+            fetch_lo: 0,
+            fetch_hi: 0,
+            first_fetch: true,
+            in_window: false,
+            window_drawn: false,
+            discard: 0,
+            lx: 0,
+            next_sprite: 0,
+            sprite_dots: 0
+        }
+    }
+
+    // The map column and pixel row the fetcher is working on.
+    fn fetch_position(&self, video: &VideoState) -> (u8, u8) {
+        if self.in_window {
+            (self.fetch_x, video.window_line)
+        } else {
+            ((video.scx / 8).wrapping_add(self.fetch_x), video.scy.wrapping_add(video.ly))
+        }
+    }
+
+    // Each of the three fetches takes two dots; the tile is pushed once the FIFO is empty.
+    fn step_fetcher(&mut self, video: &VideoState) {
+        if self.fetch_step < 6 {
+            self.fetch_step += 1;
+            let (tx, y) = self.fetch_position(video);
+            match self.fetch_step {
+                2 => {
+                    self.fetch_tile = video.vram[video.map_entry(self.in_window, tx, y)];
+                    self.fetch_attr = video.map_attr(self.in_window, tx, y);
+                },
+                4 => self.fetch_lo = video.vram[video.bg_tile_row(self.fetch_tile, self.fetch_attr, y % 8)],
+                6 => self.fetch_hi = video.vram[video.bg_tile_row(self.fetch_tile, self.fetch_attr, y % 8) + 1],
+                _ => {}
+            }
+        }
+        if self.fetch_step == 6 && self.bg_fifo.is_empty() {
+            if self.first_fetch {
+                self.first_fetch = false;
+            } else {
+                for i in 0..8 {
+                    let bit = if self.fetch_attr & 0x20 != 0 { i } else { 7 - i };
+                    let color = bitplane_pixel(self.fetch_lo, self.fetch_hi, bit);
+                    self.bg_fifo.push_back((color, self.fetch_attr));
+                }
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+            }
+            self.fetch_step = 0;
+        }
+    }
+
+    fn sprite_pending(&self, video: &VideoState) -> bool {
+        video.sprites_enabled() && self.next_sprite < video.sprites.len() &&
+            video.sprites[self.next_sprite].x as u16 <= self.lx as u16 + 8
+    }
+
+    // Mixes the fetched sprite row into the sprite FIFO, replacing pixels that are
+    // transparent or belong to a lower-priority sprite.
+    fn load_sprite(&mut self, video: &VideoState) {
+        let sprite = video.sprites[self.next_sprite];
+        self.next_sprite += 1;
+        let (lo, hi) = video.sprite_row(&sprite);
+        let priority = video.sprite_priority(&sprite);
+        // columns that are already past, for sprites hanging off the left edge.
+        let skip = (self.lx + 8 - sprite.x) as usize;
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(TRANSPARENT);
+        }
+        for col in skip..8 {
+            let color = bitplane_pixel(lo, hi, 7 - col as u8);
+            let slot = &mut self.obj_fifo[col - skip];
+            if color != 0 && (slot.color == 0 || priority < slot.priority) {
+                *slot = ObjPixel { color, attr: sprite.attr, priority };
+            }
+        }
+    }
+}
+
+impl Renderer for FifoRenderer {
+    fn start_line(&mut self, video: &mut VideoState, line: &mut [u8]) {
+        *self = FifoRenderer::new();
+        self.discard = video.scx & 7;
+        self.dot(video, line);
+    }
+
+    fn dot(&mut self, video: &mut VideoState, line: &mut [u8]) -> bool {
+        if self.lx == 160 {
+            if self.window_drawn {
+                video.window_line += 1;
+            }
+            return true;
+        }
+        if self.sprite_dots > 0 {
+            self.sprite_dots -= 1;
+            if self.sprite_dots == 0 {
+                self.load_sprite(video);
+            }
+            return false;
+        }
+        if self.discard == 0 && self.sprite_pending(video) {
+            // the sprite fetch waits for the background fetcher to finish its tile.
+            if self.fetch_step < 6 || self.bg_fifo.is_empty() {
+                self.step_fetcher(video);
+            } else {
+                self.sprite_dots = 5;
+            }
+            return false;
+        }
+        if !self.in_window && self.discard == 0 && video.window_at(self.lx) {
+            // the window restarts the fetcher from its own first column.
+            self.in_window = true;
+            self.window_drawn = true;
+            self.bg_fifo.clear();
+            self.fetch_step = 0;
+            self.fetch_x = 0;
+            if video.wx < 7 {
+                self.discard = 7 - video.wx;
+            }
+        }
+        if let Some((bg, bg_attr)) = self.bg_fifo.pop_front() {
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                let obj = self.obj_fifo.pop_front().map(|p| (p.color, p.attr));
+                let obj = if video.sprites_enabled() { obj } else { None };
+                let x = self.lx as usize;
+                line[x * 3..x * 3 + 3].copy_from_slice(&video.mix(bg, bg_attr, obj));
+                self.lx += 1;
+            }
+        }
+        self.step_fetcher(video);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ppu::{PPU, ScanlineRenderer, SHADES, SCREEN_WIDTH, DOTS_PER_LINE, OAM_SCAN_DOTS};
+
+    fn fifo_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.set_renderer(Box::new(FifoRenderer::new()));
+        ppu.write_byte(0xFF47, 0xE4);
+        ppu
+    }
+
+    fn shade(ppu: &PPU, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * SCREEN_WIDTH + x) * 3;
+        [ppu.framebuffer[i], ppu.framebuffer[i + 1], ppu.framebuffer[i + 2]]
+    }
+
+    // Dots spent in mode 3 on line 0 with the given SCX and sprites at the given X positions.
+    fn mode_3_length(scx: u8, sprites: &[u8]) -> u32 {
+        let mut ppu = fifo_ppu();
+        for (i, &x) in sprites.iter().enumerate() {
+            ppu.video.oam[i * 4] = 16;
+            ppu.video.oam[i * 4 + 1] = x;
+        }
+        ppu.write_byte(0xFF43, scx);
+        ppu.write_byte(0xFF40, 0x93);
+        ppu.tick(OAM_SCAN_DOTS);
+        let mut dots = 0;
+        while ppu.read_byte(0xFF41) & 0x03 == 3 {
+            ppu.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn fine_scroll_discards_pixels() {
+        let mut ppu = fifo_ppu();
+        // tile 0: colour 1 in the left half of every row.
+        for row in 0..8 {
+            ppu.video.vram[row * 2] = 0xF0;
+        }
+        ppu.write_byte(0xFF43, 2);
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.tick(DOTS_PER_LINE);
+        let row: Vec<[u8; 3]> = (0..10).map(|x| shade(&ppu, x, 0)).collect();
+        let (on, off) = (SHADES[1], SHADES[0]);
+        assert_eq!(row, [on, on, off, off, off, off, on, on, on, on]);
+    }
+
+    #[test]
+    fn scx_lengthens_mode_3() {
+        assert_eq!(mode_3_length(0, &[]), 172);
+        for scx in 1..8 {
+            assert_eq!(mode_3_length(scx, &[]), 172 + scx as u32);
+        }
+        assert_eq!(mode_3_length(8, &[]), 172);
+    }
+
+    #[test]
+    fn sprites_lengthen_mode_3() {
+        // a sprite costs at least 6 dots, and more the earlier it falls in a tile fetch.
+        assert_eq!(mode_3_length(0, &[15]), 172 + 6);
+        let aligned = mode_3_length(0, &[16]);
+        assert!(aligned > 172 + 6 && aligned <= 172 + 12, "{}", aligned);
+        assert!(mode_3_length(0, &[8, 8]) > aligned);
+        assert!(mode_3_length(0, &[8; 10]) >= 172 + 60);
+    }
+
+    #[test]
+    fn window_starts_mid_line() {
+        let mut ppu = fifo_ppu();
+        // tile 1 is solid colour 3; the window map (0x9C00) uses it everywhere.
+        for i in 16..32 {
+            ppu.video.vram[i] = 0xFF;
+        }
+        for i in 0x1C00..0x2000 {
+            ppu.video.vram[i] = 1;
+        }
+        ppu.write_byte(0xFF4A, 2);
+        ppu.write_byte(0xFF4B, 87);
+        ppu.write_byte(0xFF40, 0xF1);
+        ppu.tick(DOTS_PER_LINE * 3);
+        assert_eq!(shade(&ppu, 80, 1), SHADES[0]);
+        assert_eq!(shade(&ppu, 79, 2), SHADES[0]);
+        assert_eq!(shade(&ppu, 80, 2), SHADES[3]);
+        assert_eq!(shade(&ppu, 159, 2), SHADES[3]);
+        assert_eq!(ppu.video.window_line, 1);
+    }
+
+    // Fills VRAM and OAM from a fixed pseudo-random sequence, then renders a frame
+    // with scrolling, the window and sprites all in use.
+    fn render_frame(renderer: Box<dyn Renderer>) -> Vec<u8> {
+        let mut ppu = PPU::new();
+        ppu.set_renderer(renderer);
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+        for i in 0..0x1800 {
+            ppu.video.vram[i] = next();
+        }
+        for i in 0x1800..0x2000 {
+            ppu.video.vram[i] = next() & 0x3F;
+        }
+        for i in 0..40 {
+            let sprite = [next() % 160 + 16, next() % 168 + 8, next() & 0x3F, next() & 0xF0];
+            ppu.video.oam[i * 4..i * 4 + 4].copy_from_slice(&sprite);
+        }
+        for &(addr, val) in [(0xFF42, 37), (0xFF43, 13), (0xFF47, 0xE4), (0xFF48, 0xD2),
+                             (0xFF49, 0x1B), (0xFF4A, 60), (0xFF4B, 90), (0xFF40, 0xF3)].iter() {
+            ppu.write_byte(addr, val);
+        }
+        ppu.tick(DOTS_PER_LINE * 154);
+        ppu.framebuffer.clone()
+    }
+
+    #[test]
+    fn matches_scanline_renderer_on_static_frame() {
+        let scanline = render_frame(Box::new(ScanlineRenderer::new()));
+        let fifo = render_frame(Box::new(FifoRenderer::new()));
+        let first_difference = scanline.iter().zip(fifo.iter()).position(|(a, b)| a != b);
+        assert_eq!(first_difference.map(|i| (i / 3 % SCREEN_WIDTH, i / 3 / SCREEN_WIDTH)), None);
+    }
+}
\ No newline at end of file
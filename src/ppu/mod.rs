@@ -0,0 +1,822 @@
+mod scanline;
+mod fifo;
+pub mod compat;
+pub use self::scanline::ScanlineRenderer;
+pub use self::fifo::FifoRenderer;
+
+use mmu::{INT_VBLANK, INT_STAT};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+// 154 lines, of which 10 are VBlank.
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * 154;
+const OAM_SCAN_DOTS: u32 = 80;
+// LY only reads 153 for the first M-cycle of line 153, and 0 for the rest of it.
+const LINE_153_DOTS: u32 = 4;
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// RGB for DMG colour numbers 0-3.
+const SHADES: [[u8; 3]; 4] = [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]];
+
+// Draws the pixels of a line during mode 3. Implementations decide how long
+// mode 3 lasts, which is what makes them differ in mid-line accuracy.
+pub trait Renderer {
+    // Called on the first dot of mode 3.
+    fn start_line(&mut self, video: &mut VideoState, line: &mut [u8]);
+    // Advances mode 3 by one dot; returns true once the line is finished and HBlank can start.
+    fn dot(&mut self, video: &mut VideoState, line: &mut [u8]) -> bool;
+}
+
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attr: u8,
+    pub index: u8 // position in OAM
+}
+
+// Scales an RGB555 palette entry (little-endian in palette RAM) up to RGB24.
+fn rgb555(lo: u8, hi: u8) -> [u8; 3] {
+    let color = (lo as u16) | ((hi as u16) << 8);
+    let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    [scale(color & 0x1F), scale((color >> 5) & 0x1F), scale((color >> 10) & 0x1F)]
+}
+
+// Everything a renderer reads: video memory, the LCD registers and per-line state.
+pub struct VideoState {
+    pub cgb: bool,
+    // a DMG game on CGB hardware: DMG shades are looked up in CGB palette RAM.
+    pub compat: bool,
+    // both CGB banks; on DMG only the first 0x2000 bytes are used.
+    pub vram: [u8; 0x4000],
+    pub oam: [u8; 0xA0],
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    // CGB palette RAM: eight palettes of four RGB555 colours each.
+    pub bg_palettes: [u8; 64],
+    pub obj_palettes: [u8; 64],
+    // the window has its own line counter, which only advances on lines where it was drawn.
+    pub window_line: u8,
+    pub window_triggered: bool, // set once LY has matched WY this frame
+    // sprites on this line, in drawing priority order.
+    pub sprites: Vec<Sprite>
+}
+
+impl VideoState {
+    pub fn sprite_height(&self) -> u8 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    // On DMG, LCDC.0 blanks the BG and window; on CGB it only takes away their
+    // priority over sprites.
+    pub fn bg_enabled(&self) -> bool {
+        self.lcdc & 0x01 != 0
+    }
+
+    pub fn sprites_enabled(&self) -> bool {
+        self.lcdc & 0x02 != 0
+    }
+
+    // Whether the window covers screen column `x` on this line.
+    pub fn window_at(&self, x: u8) -> bool {
+        self.lcdc & 0x20 != 0 && (self.cgb || self.bg_enabled()) && self.window_triggered && x as u16 + 7 >= self.wx as u16
+    }
+
+    // Index into VRAM of the BG map entry at tile column `tx` and pixel row `y` of a map.
+    pub fn map_entry(&self, window: bool, tx: u8, y: u8) -> usize {
+        let select = if window { 0x40 } else { 0x08 };
+        let map = if self.lcdc & select != 0 { 0x1C00 } else { 0x1800 };
+        map + (y as usize / 8) * 32 + (tx as usize & 31)
+    }
+
+    // The CGB attributes of a BG map entry, which sit at the same place in VRAM bank 1:
+    // palette (bits 0-2), tile bank (3), X flip (5), Y flip (6) and priority over sprites (7).
+    pub fn map_attr(&self, window: bool, tx: u8, y: u8) -> u8 {
+        if self.cgb { self.vram[0x2000 + self.map_entry(window, tx, y)] } else { 0 }
+    }
+
+    // Offset into VRAM of one row of a BG/window tile, honouring the LCDC.4 addressing
+    // mode and the tile's bank and Y flip attributes.
+    pub fn bg_tile_row(&self, tile: u8, attr: u8, row: u8) -> usize {
+        let base = if self.lcdc & 0x10 != 0 {
+            (tile as usize) * 16
+        } else {
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        };
+        let bank = if attr & 0x08 != 0 { 0x2000 } else { 0 };
+        let row = if attr & 0x40 != 0 { 7 - row } else { row };
+        bank + base + (row as usize) * 2
+    }
+
+    // The two bitplanes of a sprite's row on line LY, already flipped horizontally
+    // if needed, so bit 7 is always the leftmost pixel.
+    pub fn sprite_row(&self, sprite: &Sprite) -> (u8, u8) {
+        let height = self.sprite_height();
+        let mut row = self.ly.wrapping_sub(sprite.y.wrapping_sub(16));
+        row &= height - 1;
+        if sprite.attr & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let bank = if self.cgb && sprite.attr & 0x08 != 0 { 0x2000 } else { 0 };
+        let addr = bank + (tile as usize) * 16 + (row as usize) * 2;
+        let (lo, hi) = (self.vram[addr], self.vram[addr + 1]);
+        if sprite.attr & 0x20 != 0 {
+            (lo.reverse_bits(), hi.reverse_bits())
+        } else {
+            (lo, hi)
+        }
+    }
+
+    // Where a sprite stands when sprites overlap; lower wins. The DMG goes by X
+    // coordinate, then OAM position, while the CGB only looks at OAM position.
+    pub fn sprite_priority(&self, sprite: &Sprite) -> u16 {
+        if self.cgb {
+            sprite.index as u16
+        } else {
+            ((sprite.x as u16) << 8) | sprite.index as u16
+        }
+    }
+
+    // Combines a BG colour number and its map attributes with the winning sprite
+    // pixel (colour number and attributes), if any, into the final RGB value.
+    pub fn mix(&self, bg: u8, bg_attr: u8, obj: Option<(u8, u8)>) -> [u8; 3] {
+        if self.cgb {
+            return self.mix_cgb(bg, bg_attr, obj);
+        }
+        let bg = if self.bg_enabled() { bg } else { 0 };
+        if let Some((color, attr)) = obj {
+            // OBJ-to-BG priority: the sprite goes behind BG colours 1-3.
+            if color != 0 && (attr & 0x80 == 0 || bg == 0) {
+                let (palette, number) = if attr & 0x10 != 0 { (self.obp1, 1) } else { (self.obp0, 0) };
+                return self.shade_rgb(&self.obj_palettes, number, (palette >> (color * 2)) & 0x03);
+            }
+        }
+        self.shade_rgb(&self.bg_palettes, 0, (self.bgp >> (bg * 2)) & 0x03)
+    }
+
+    // RGB for a DMG shade from the given palette.
+    fn shade_rgb(&self, palettes: &[u8; 64], palette: usize, shade: u8) -> [u8; 3] {
+        if self.compat {
+            let i = palette * 8 + shade as usize * 2;
+            rgb555(palettes[i], palettes[i + 1])
+        } else {
+            SHADES[shade as usize]
+        }
+    }
+
+    fn mix_cgb(&self, bg: u8, bg_attr: u8, obj: Option<(u8, u8)>) -> [u8; 3] {
+        if let Some((color, attr)) = obj {
+            // BG colours 1-3 cover the sprite if either the map entry or the sprite asks
+            // for it, unless LCDC.0 is clear.
+            let bg_wins = self.bg_enabled() && bg != 0 && (bg_attr & 0x80 != 0 || attr & 0x80 != 0);
+            if color != 0 && !bg_wins {
+                let i = ((attr & 0x07) * 8 + color * 2) as usize;
+                return rgb555(self.obj_palettes[i], self.obj_palettes[i + 1]);
+            }
+        }
+        let i = ((bg_attr & 0x07) * 8 + bg * 2) as usize;
+        rgb555(self.bg_palettes[i], self.bg_palettes[i + 1])
+    }
+}
+
+pub fn bitplane_pixel(lo: u8, hi: u8, bit: u8) -> u8 {
+    (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+}
+
+pub struct PPU {
+    pub video: VideoState,
+    stat: u8, // only the interrupt enable bits (3-6) are stored; mode and LYC=LY are derived
+    mode: u8,
+    dot: u32, // position within the current line, 0-455
+    // STAT interrupts fire on the rising edge of the OR of all enabled conditions.
+    stat_line: bool,
+    renderer: Box<dyn Renderer>,
+    pub framebuffer: Vec<u8>, // 160x144 RGB24
+    pub frame_ready: bool,
+    pub hblank_started: bool, // set on entering HBlank on a visible line, for HDMA
+    pub interrupt: u8,
+    // CGB only: VBK (0xFF4F) and the palette index registers BCPS/OCPS (0xFF68/0xFF6A),
+    // whose bit 7 makes the index advance on every data register write.
+    vram_bank: usize,
+    bcps: u8,
+    ocps: u8
+}
+
+impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            video: VideoState {
+                cgb: false,
+                compat: false,
+                vram: [0; 0x4000],
+                oam: [0; 0xA0],
+                lcdc: 0,
+                scy: 0,
+                scx: 0,
+                ly: 0,
+                lyc: 0,
+                bgp: 0,
+                obp0: 0,
+                obp1: 0,
+                wy: 0,
+                wx: 0,
+                bg_palettes: [0xFF; 64],
+                obj_palettes: [0xFF; 64],
+                window_line: 0,
+                window_triggered: false,
+                sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE)
+            },
+            stat: 0,
+            mode: 0,
+            dot: 0,
+            stat_line: false,
+            renderer: Box::new(ScanlineRenderer::new()),
+            framebuffer: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            frame_ready: false,
+            hblank_started: false,
+            interrupt: 0,
+            vram_bank: 0,
+            bcps: 0,
+            ocps: 0
+        }
+    }
+
+    // The boot ROM hands over with the LCD on, partway through line 153.
+    pub fn set_post_boot_state(&mut self) {
+        self.write_byte(0xFF40, 0x91);
+        self.video.ly = 0;
+        self.mode = 1;
+        self.dot = DOTS_PER_LINE / 2;
+        self.update_stat_line();
+    }
+
+    // Swaps the mode 3 implementation; takes effect from the next line.
+    pub fn set_renderer(&mut self, renderer: Box<dyn Renderer>) {
+        self.renderer = renderer;
+    }
+
+    fn lcd_on(&self) -> bool {
+        self.video.lcdc & 0x80 != 0
+    }
+
+    // The CPU can't see VRAM while it's being read for mode 3, nor OAM during modes 2 and 3.
+    fn vram_accessible(&self) -> bool {
+        !self.lcd_on() || self.mode != 3
+    }
+
+    // Mode 0, which is also where the PPU sits with the LCD off.
+    pub fn in_hblank(&self) -> bool {
+        !self.lcd_on() || self.mode == 0
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_on() || self.mode < 2
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        if !self.vram_accessible() {
+            return 0xFF;
+        }
+        self.peek_vram(addr)
+    }
+
+    // The current bank's contents, whatever mode the PPU is in.
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        self.video.vram[self.vram_bank * 0x2000 + (addr - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, val: u8) {
+        if self.vram_accessible() {
+            self.video.vram[self.vram_bank * 0x2000 + (addr - 0x8000) as usize] = val;
+        }
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        if !self.oam_accessible() {
+            return 0xFF;
+        }
+        self.video.oam[(addr - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, val: u8) {
+        if self.oam_accessible() {
+            self.video.oam[(addr - 0xFE00) as usize] = val;
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        let v = &self.video;
+        match addr {
+            0xFF40 => v.lcdc,
+            0xFF41 => {
+                let coincidence = if v.ly == v.lyc { 0x04 } else { 0 };
+                let mode = if self.lcd_on() { self.mode } else { 0 };
+                0x80 | self.stat | coincidence | mode
+            },
+            0xFF42 => v.scy,
+            0xFF43 => v.scx,
+            0xFF44 => v.ly,
+            0xFF45 => v.lyc,
+            0xFF47 => v.bgp,
+            0xFF48 => v.obp0,
+            0xFF49 => v.obp1,
+            0xFF4A => v.wy,
+            0xFF4B => v.wx,
+            0xFF4F if v.cgb => 0xFE | self.vram_bank as u8,
+            0xFF68 if v.cgb => 0x40 | self.bcps,
+            0xFF69 if v.cgb => self.read_palette(&v.bg_palettes, self.bcps),
+            0xFF6A if v.cgb => 0x40 | self.ocps,
+            0xFF6B if v.cgb => self.read_palette(&v.obj_palettes, self.ocps),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 => {
+                let was_on = self.lcd_on();
+                self.video.lcdc = val;
+                if was_on && !self.lcd_on() {
+                    self.video.ly = 0;
+                    self.dot = 0;
+                    self.mode = 0;
+                    self.stat_line = false;
+                } else if !was_on && self.lcd_on() {
+                    self.video.ly = 0;
+                    self.dot = 0;
+                    self.start_line();
+                }
+            },
+            0xFF41 => self.stat = val & 0x78,
+            0xFF42 => self.video.scy = val,
+            0xFF43 => self.video.scx = val,
+            0xFF44 => {}, // LY is read-only
+            0xFF45 => self.video.lyc = val,
+            0xFF47 => self.video.bgp = val,
+            0xFF48 => self.video.obp0 = val,
+            0xFF49 => self.video.obp1 = val,
+            0xFF4A => self.video.wy = val,
+            0xFF4B => self.video.wx = val,
+            0xFF4F if self.video.cgb => self.vram_bank = (val & 0x01) as usize,
+            0xFF68 if self.video.cgb => self.bcps = val & 0xBF,
+            0xFF69 if self.video.cgb => {
+                let accessible = self.vram_accessible();
+                PPU::write_palette(&mut self.video.bg_palettes, &mut self.bcps, val, accessible);
+            },
+            0xFF6A if self.video.cgb => self.ocps = val & 0xBF,
+            0xFF6B if self.video.cgb => {
+                let accessible = self.vram_accessible();
+                PPU::write_palette(&mut self.video.obj_palettes, &mut self.ocps, val, accessible);
+            },
+            _ => {}
+        }
+        if self.lcd_on() {
+            self.update_stat_line();
+        }
+    }
+
+    // Palette RAM is locked during mode 3, just like VRAM.
+    fn read_palette(&self, palettes: &[u8; 64], index: u8) -> u8 {
+        if self.vram_accessible() { palettes[(index & 0x3F) as usize] } else { 0xFF }
+    }
+
+    // The index still advances when the write itself is blocked.
+    fn write_palette(palettes: &mut [u8; 64], index: &mut u8, val: u8, accessible: bool) {
+        if accessible {
+            palettes[(*index & 0x3F) as usize] = val;
+        }
+        if *index & 0x80 != 0 {
+            *index = 0x80 | (index.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    // Advances the PPU by `dots` dots (T-cycles at normal speed).
+    pub fn tick(&mut self, dots: u32) {
+        if !self.lcd_on() {
+            return;
+        }
+        for _ in 0..dots {
+            self.tick_dot();
+        }
+    }
+
+    fn tick_dot(&mut self) {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            // at the end of line 153, LY is already 0.
+            if self.mode != 1 || self.video.ly != 0 {
+                self.video.ly += 1;
+            }
+            self.start_line();
+        } else if self.video.ly == 153 && self.dot == LINE_153_DOTS {
+            self.video.ly = 0;
+        } else if self.mode != 1 && self.video.ly < SCREEN_HEIGHT as u8 {
+            let start = (self.video.ly as usize) * SCREEN_WIDTH * 3;
+            let line = &mut self.framebuffer[start..start + SCREEN_WIDTH * 3];
+            if self.dot == OAM_SCAN_DOTS {
+                self.mode = 3;
+                self.renderer.start_line(&mut self.video, line);
+            } else if self.mode == 3 && self.renderer.dot(&mut self.video, line) {
+                self.mode = 0;
+                self.hblank_started = true;
+            }
+        }
+        self.update_stat_line();
+    }
+
+    fn start_line(&mut self) {
+        if self.video.ly == 0 {
+            self.video.window_line = 0;
+            self.video.window_triggered = false;
+        }
+        if self.video.ly < SCREEN_HEIGHT as u8 {
+            self.mode = 2;
+            if self.video.ly == self.video.wy {
+                self.video.window_triggered = true;
+            }
+            self.scan_oam();
+        } else if self.video.ly == SCREEN_HEIGHT as u8 {
+            self.mode = 1;
+            self.interrupt |= INT_VBLANK;
+            self.frame_ready = true;
+        }
+        self.update_stat_line();
+    }
+
+    fn update_stat_line(&mut self) {
+        let line = (self.stat & 0x40 != 0 && self.video.ly == self.video.lyc) ||
+            (self.stat & 0x08 != 0 && self.mode == 0) ||
+            (self.stat & 0x10 != 0 && self.mode == 1) ||
+            (self.stat & 0x20 != 0 && self.mode == 2);
+        if line && !self.stat_line {
+            self.interrupt |= INT_STAT;
+        }
+        self.stat_line = line;
+    }
+
+    // Mode 2: pick the first ten sprites in OAM that overlap this line.
+    fn scan_oam(&mut self) {
+        let v = &mut self.video;
+        v.sprites.clear();
+        let height = v.sprite_height() as i16;
+        let line = v.ly as i16;
+        for i in 0..40 {
+            let y = v.oam[i * 4] as i16 - 16;
+            if line >= y && line < y + height {
+                v.sprites.push(Sprite {
+                    y: v.oam[i * 4],
+                    x: v.oam[i * 4 + 1],
+                    tile: v.oam[i * 4 + 2],
+                    attr: v.oam[i * 4 + 3],
+                    index: i as u8
+                });
+                if v.sprites.len() == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+        // the renderers fetch sprites left to right; sprite_priority settles overlaps.
+        // (the sort is stable, so OAM order is kept for equal X.)
+        v.sprites.sort_by_key(|s| s.x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lcd_on() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu
+    }
+
+    fn mode(ppu: &PPU) -> u8 {
+        ppu.read_byte(0xFF41) & 0x03
+    }
+
+    #[test]
+    fn modes_follow_line_timing() {
+        let mut ppu = lcd_on();
+        assert_eq!(mode(&ppu), 2);
+        ppu.tick(OAM_SCAN_DOTS - 1);
+        assert_eq!(mode(&ppu), 2);
+        ppu.tick(1);
+        assert_eq!(mode(&ppu), 3);
+        ppu.tick(172);
+        assert_eq!(mode(&ppu), 0);
+        assert!(ppu.hblank_started);
+        ppu.tick(DOTS_PER_LINE - OAM_SCAN_DOTS - 172 - 1);
+        assert_eq!((mode(&ppu), ppu.read_byte(0xFF44)), (0, 0));
+        ppu.tick(1);
+        assert_eq!((mode(&ppu), ppu.read_byte(0xFF44)), (2, 1));
+    }
+
+    #[test]
+    fn vblank_and_frame_length() {
+        let mut ppu = lcd_on();
+        ppu.tick(DOTS_PER_LINE * 144 - 1);
+        assert_eq!(ppu.interrupt & INT_VBLANK, 0);
+        ppu.tick(1);
+        assert_eq!((mode(&ppu), ppu.read_byte(0xFF44)), (1, 144));
+        assert_eq!(ppu.interrupt & INT_VBLANK, INT_VBLANK);
+        assert!(ppu.frame_ready);
+        // LY reads 0 for most of line 153.
+        ppu.tick(DOTS_PER_LINE * 9 + LINE_153_DOTS);
+        assert_eq!((mode(&ppu), ppu.read_byte(0xFF44)), (1, 0));
+        ppu.tick(DOTS_PER_LINE - LINE_153_DOTS);
+        assert_eq!((mode(&ppu), ppu.read_byte(0xFF44)), (2, 0));
+    }
+
+    #[test]
+    fn lyc_stat_interrupt() {
+        let mut ppu = lcd_on();
+        ppu.write_byte(0xFF45, 3);
+        ppu.write_byte(0xFF41, 0x40);
+        ppu.tick(DOTS_PER_LINE * 3 - 1);
+        assert_eq!(ppu.interrupt & INT_STAT, 0);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0);
+        ppu.tick(1);
+        assert_eq!(ppu.interrupt & INT_STAT, INT_STAT);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn stat_interrupt_needs_rising_edge() {
+        let mut ppu = lcd_on();
+        // HBlank and LYC=LY overlap on line 0, so entering mode 0 is not a new edge.
+        ppu.write_byte(0xFF41, 0x48);
+        assert_eq!(ppu.interrupt & INT_STAT, INT_STAT);
+        ppu.interrupt = 0;
+        ppu.tick(DOTS_PER_LINE - 1);
+        assert_eq!(ppu.interrupt & INT_STAT, 0);
+        // line 1 drops LYC=LY during mode 2, so its HBlank raises the line again.
+        ppu.tick(DOTS_PER_LINE);
+        assert_eq!(ppu.interrupt & INT_STAT, INT_STAT);
+    }
+
+    #[test]
+    fn lcd_off_resets_ly_and_unlocks_memory() {
+        let mut ppu = lcd_on();
+        ppu.tick(DOTS_PER_LINE * 5 + OAM_SCAN_DOTS + 1);
+        assert_eq!(ppu.read_vram(0x8000), 0xFF);
+        assert_eq!(ppu.read_oam(0xFE00), 0xFF);
+        ppu.write_byte(0xFF40, 0x11);
+        assert_eq!(ppu.read_byte(0xFF44), 0);
+        assert_eq!(mode(&ppu), 0);
+        ppu.write_vram(0x8000, 0x12);
+        assert_eq!(ppu.read_vram(0x8000), 0x12);
+        ppu.tick(DOTS_PER_LINE * 10);
+        assert_eq!(ppu.read_byte(0xFF44), 0);
+    }
+
+    #[test]
+    fn renders_background_tile() {
+        let mut ppu = lcd_on();
+        ppu.write_byte(0xFF47, 0xE4);
+        // tile 0 row 0 is colour 3 in its first pixel; the map points everything at tile 0.
+        ppu.video.vram[0] = 0x80;
+        ppu.video.vram[1] = 0x80;
+        ppu.tick(DOTS_PER_LINE);
+        assert_eq!(&ppu.framebuffer[0..3], &SHADES[3]);
+        assert_eq!(&ppu.framebuffer[3..6], &SHADES[0]);
+    }
+
+    #[test]
+    fn vbk_selects_vram_bank() {
+        let mut ppu = PPU::new();
+        ppu.video.cgb = true;
+        ppu.write_byte(0xFF4F, 0x01);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFF);
+        ppu.write_vram(0x8000, 0xAA);
+        ppu.write_vram(0x9FFF, 0xBB);
+        ppu.write_byte(0xFF4F, 0xFE);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFE);
+        assert_eq!(ppu.read_vram(0x8000), 0x00);
+        assert_eq!(ppu.read_vram(0x9FFF), 0x00);
+        ppu.write_vram(0x8000, 0x55);
+        ppu.write_byte(0xFF4F, 0x01);
+        assert_eq!(ppu.read_vram(0x8000), 0xAA);
+        assert_eq!(ppu.read_vram(0x9FFF), 0xBB);
+    }
+
+    #[test]
+    fn dmg_ignores_vbk() {
+        let mut ppu = PPU::new();
+        ppu.write_byte(0xFF4F, 0x01);
+        ppu.write_vram(0x8000, 0xAA);
+        assert_eq!(ppu.read_byte(0xFF4F), 0xFF);
+        assert_eq!(ppu.video.vram[0x0000], 0xAA);
+        assert_eq!(ppu.video.vram[0x2000], 0x00);
+    }
+
+    // BG colour c of palette p is grey level 4p+c; OBJ colours count down from white.
+    fn bg_color(p: u16, c: u16) -> [u8; 3] {
+        let color = (p * 4 + c) * 0x0421;
+        rgb555(color as u8, (color >> 8) as u8)
+    }
+
+    fn obj_color(p: u16, c: u16) -> [u8; 3] {
+        let color = (31 - (p * 4 + c)) * 0x0421;
+        rgb555(color as u8, (color >> 8) as u8)
+    }
+
+    fn cgb_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.video.cgb = true;
+        ppu.write_byte(0xFF68, 0x80);
+        ppu.write_byte(0xFF6A, 0x80);
+        for i in 0..32 {
+            let bg = i * 0x0421;
+            let obj = (31 - i) * 0x0421;
+            ppu.write_byte(0xFF69, bg as u8);
+            ppu.write_byte(0xFF69, (bg >> 8) as u8);
+            ppu.write_byte(0xFF6B, obj as u8);
+            ppu.write_byte(0xFF6B, (obj >> 8) as u8);
+        }
+        ppu
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * SCREEN_WIDTH + x) * 3;
+        [ppu.framebuffer[i], ppu.framebuffer[i + 1], ppu.framebuffer[i + 2]]
+    }
+
+    // Turns the LCD on with `lcdc` and runs through line `y`.
+    fn render_to(ppu: &mut PPU, lcdc: u8, y: usize) {
+        ppu.write_byte(0xFF40, lcdc);
+        ppu.tick(DOTS_PER_LINE * (y as u32 + 1));
+    }
+
+    #[test]
+    fn palette_index_auto_increments_and_wraps() {
+        let mut ppu = PPU::new();
+        ppu.video.cgb = true;
+        ppu.write_byte(0xFF68, 0xBE);
+        for &val in [0x11, 0x22, 0x33].iter() {
+            ppu.write_byte(0xFF69, val);
+        }
+        assert_eq!(ppu.read_byte(0xFF68), 0xC1);
+        assert_eq!((ppu.video.bg_palettes[0x3E], ppu.video.bg_palettes[0x3F]), (0x11, 0x22));
+        assert_eq!(ppu.video.bg_palettes[0], 0x33);
+        // without bit 7 the index stays put, and reads never move it.
+        ppu.write_byte(0xFF6A, 0x05);
+        ppu.write_byte(0xFF6B, 0x44);
+        ppu.write_byte(0xFF6B, 0x55);
+        assert_eq!(ppu.read_byte(0xFF6B), 0x55);
+        assert_eq!(ppu.read_byte(0xFF6A), 0x45);
+        assert_eq!(ppu.video.obj_palettes[5], 0x55);
+        assert_eq!(ppu.video.obj_palettes[6], 0xFF);
+    }
+
+    #[test]
+    fn palette_ram_locked_during_mode_3() {
+        let mut ppu = PPU::new();
+        ppu.video.cgb = true;
+        ppu.write_byte(0xFF68, 0x80);
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.tick(OAM_SCAN_DOTS);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x03, 3);
+        assert_eq!(ppu.read_byte(0xFF69), 0xFF);
+        // the write is dropped, but the index still advances.
+        ppu.write_byte(0xFF69, 0x12);
+        assert_eq!(ppu.read_byte(0xFF68), 0xC1);
+        assert_eq!(ppu.video.bg_palettes[0], 0xFF);
+        ppu.tick(DOTS_PER_LINE - OAM_SCAN_DOTS);
+        ppu.write_byte(0xFF69, 0x12);
+        assert_eq!(ppu.video.bg_palettes[1], 0x12);
+    }
+
+    // Tile 0 has colour 1 in the top-left pixel in bank 0, colour 2 in bank 1.
+    fn attr_ppu(attr: u8) -> PPU {
+        let mut ppu = cgb_ppu();
+        ppu.video.vram[0x0000] = 0x80;
+        ppu.video.vram[0x2001] = 0x80;
+        ppu.video.vram[0x2000 + 0x1800] = attr;
+        ppu
+    }
+
+    #[test]
+    fn bg_attributes() {
+        let mut ppu = attr_ppu(0x00);
+        render_to(&mut ppu, 0x91, 7);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(0, 1));
+        assert_eq!(pixel(&ppu, 7, 0), bg_color(0, 0));
+        assert_eq!(pixel(&ppu, 0, 7), bg_color(0, 0));
+        // palette
+        let mut ppu = attr_ppu(0x05);
+        render_to(&mut ppu, 0x91, 0);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(5, 1));
+        assert_eq!(pixel(&ppu, 1, 0), bg_color(5, 0));
+        // tile data from bank 1
+        let mut ppu = attr_ppu(0x08);
+        render_to(&mut ppu, 0x91, 0);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(0, 2));
+        // X flip
+        let mut ppu = attr_ppu(0x20);
+        render_to(&mut ppu, 0x91, 0);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(0, 0));
+        assert_eq!(pixel(&ppu, 7, 0), bg_color(0, 1));
+        // Y flip
+        let mut ppu = attr_ppu(0x40);
+        render_to(&mut ppu, 0x91, 7);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(0, 0));
+        assert_eq!(pixel(&ppu, 0, 7), bg_color(0, 1));
+    }
+
+    // A sprite of solid colour 3 in OBJ palette 2, over the top-left BG tile.
+    fn priority_ppu(attr: u8) -> PPU {
+        let mut ppu = attr_ppu(attr);
+        for i in 16..32 {
+            ppu.video.vram[i] = 0xFF;
+        }
+        ppu.video.oam[0..4].copy_from_slice(&[16, 8, 1, 0x02]);
+        ppu
+    }
+
+    #[test]
+    fn bg_priority_attribute() {
+        let mut ppu = priority_ppu(0x00);
+        render_to(&mut ppu, 0x93, 0);
+        assert_eq!(pixel(&ppu, 0, 0), obj_color(2, 3));
+        // the attribute only puts BG colours 1-3 over the sprite.
+        let mut ppu = priority_ppu(0x80);
+        render_to(&mut ppu, 0x93, 0);
+        assert_eq!(pixel(&ppu, 0, 0), bg_color(0, 1));
+        assert_eq!(pixel(&ppu, 1, 0), obj_color(2, 3));
+        // with LCDC.0 clear sprites always win.
+        let mut ppu = priority_ppu(0x80);
+        render_to(&mut ppu, 0x92, 0);
+        assert_eq!(pixel(&ppu, 0, 0), obj_color(2, 3));
+    }
+
+    // Sprite 0 (colour 1) at X 9 overlaps sprite 1 (colour 3) at X 8 from screen X 1 to 7.
+    fn overlapping_sprites(ppu: &mut PPU) {
+        for i in 0..8 {
+            ppu.video.vram[16 + i * 2] = 0xFF;
+            ppu.video.vram[32 + i * 2] = 0xFF;
+            ppu.video.vram[33 + i * 2] = 0xFF;
+        }
+        ppu.video.oam[0..8].copy_from_slice(&[16, 9, 1, 0x00, 16, 8, 2, 0x00]);
+        render_to(ppu, 0x93, 0);
+    }
+
+    #[test]
+    fn cgb_sprite_priority_by_oam_index() {
+        let mut ppu = cgb_ppu();
+        overlapping_sprites(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), obj_color(0, 3));
+        assert_eq!(pixel(&ppu, 1, 0), obj_color(0, 1));
+    }
+
+    #[test]
+    fn dmg_sprite_priority_by_x() {
+        let mut ppu = PPU::new();
+        ppu.write_byte(0xFF48, 0xE4);
+        overlapping_sprites(&mut ppu);
+        assert_eq!(pixel(&ppu, 1, 0), SHADES[3]);
+        assert_eq!(pixel(&ppu, 7, 0), SHADES[3]);
+        assert_eq!(pixel(&ppu, 8, 0), SHADES[1]);
+    }
+
+    // A Y-flipped 8x16 sprite covering lines 0-15, whose tile has only row 5 set.
+    // On line 10, the scan sees it as 8x16, then LCDC.2 drops to 8x8 before mode 3.
+    fn render_after_height_change(renderer: Box<dyn Renderer>) -> [u8; 3] {
+        let mut ppu = PPU::new();
+        ppu.set_renderer(renderer);
+        ppu.video.oam[0..4].copy_from_slice(&[16, 8, 0, 0x40]);
+        ppu.video.vram[10] = 0xFF;
+        ppu.video.vram[11] = 0xFF;
+        ppu.write_byte(0xFF48, 0xE4);
+        ppu.write_byte(0xFF40, 0x86);
+        ppu.tick(DOTS_PER_LINE * 10);
+        assert_eq!(ppu.video.sprites.len(), 1);
+        ppu.write_byte(0xFF40, 0x82);
+        ppu.tick(DOTS_PER_LINE);
+        let start = 10 * SCREEN_WIDTH * 3;
+        [ppu.framebuffer[start], ppu.framebuffer[start + 1], ppu.framebuffer[start + 2]]
+    }
+
+    #[test]
+    fn scanline_sprite_height_change_after_oam_scan() {
+        assert_eq!(render_after_height_change(Box::new(ScanlineRenderer::new())), SHADES[3]);
+    }
+
+    #[test]
+    fn fifo_sprite_height_change_after_oam_scan() {
+        assert_eq!(render_after_height_change(Box::new(FifoRenderer::new())), SHADES[3]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,63 @@
+use ppu::{Renderer, VideoState, Sprite, SCREEN_WIDTH, bitplane_pixel};
+
+// Draws the whole line at once with the register values from the start of mode 3,
+// and estimates how long mode 3 would have taken.
+pub struct ScanlineRenderer {
+    dots_left: u32
+}
+
+impl ScanlineRenderer {
+    pub fn new() -> ScanlineRenderer {
+        ScanlineRenderer {
+            dots_left: 0
+        }
+    }
+}
+
+// The colour number and attributes of the highest-priority opaque sprite pixel at `x`.
+fn sprite_pixel(video: &VideoState, x: i16) -> Option<(u8, u8)> {
+    video.sprites.iter().filter_map(|sprite: &Sprite| {
+        let sx = sprite.x as i16 - 8;
+        if x < sx || x >= sx + 8 {
+            return None;
+        }
+        let (lo, hi) = video.sprite_row(sprite);
+        let color = bitplane_pixel(lo, hi, 7 - (x - sx) as u8);
+        if color != 0 { Some((color, sprite.attr, video.sprite_priority(sprite))) } else { None }
+    }).min_by_key(|&(_, _, priority)| priority).map(|(color, attr, _)| (color, attr))
+}
+
+impl Renderer for ScanlineRenderer {
+    fn start_line(&mut self, video: &mut VideoState, line: &mut [u8]) {
+        let mut window_drawn = false;
+        for x in 0..SCREEN_WIDTH {
+            let window = video.window_at(x as u8);
+            let (map_x, map_y) = if window {
+                window_drawn = true;
+                (x as u8 + 7 - video.wx, video.window_line)
+            } else {
+                (video.scx.wrapping_add(x as u8), video.scy.wrapping_add(video.ly))
+            };
+            let tile = video.vram[video.map_entry(window, map_x / 8, map_y)];
+            let attr = video.map_attr(window, map_x / 8, map_y);
+            let row = video.bg_tile_row(tile, attr, map_y % 8);
+            let bit = if attr & 0x20 != 0 { map_x % 8 } else { 7 - map_x % 8 };
+            let bg = bitplane_pixel(video.vram[row], video.vram[row + 1], bit);
+            let obj = if video.sprites_enabled() { sprite_pixel(video, x as i16) } else { None };
+            line[x * 3..x * 3 + 3].copy_from_slice(&video.mix(bg, attr, obj));
+        }
+        if window_drawn {
+            video.window_line += 1;
+        }
+        // roughly: the fine-scroll discard and every sprite each stall the pixel pipeline.
+        self.dots_left = 172 + (video.scx as u32 & 7) + 6 * video.sprites.len() as u32;
+        if window_drawn {
+            self.dots_left += 6;
+        }
+    }
+
+    fn dot(&mut self, _video: &mut VideoState, _line: &mut [u8]) -> bool {
+        self.dots_left -= 1;
+        self.dots_left == 0
+    }
+}
\ No newline at end of file
@@ -22,7 +22,7 @@ impl Registers {
     }
     pub fn set_af(&mut self, val: u16) {
         self.a = (val >> 8) as u8;
-        self.f = (val & 0xFF) as u8;
+        self.f = (val & 0xF0) as u8; // the low nibble of F doesn't exist
     }
 
     pub fn bc(&self) -> u16 {
@@ -42,11 +42,11 @@ impl Registers {
     }
 
     pub fn hl(&self) -> u16 {
-        ((self.a as u16) << 8) | (self.f as u16)
+        ((self.h as u16) << 8) | (self.l as u16)
     }
     pub fn set_hl(&mut self, val: u16) {
-        self.a = (val >> 8) as u8;
-        self.f = (val & 0xFF) as u8;
+        self.h = (val >> 8) as u8;
+        self.l = (val & 0xFF) as u8;
     }
 
     pub fn get_reg(&mut self, code: u8) -> u8 {
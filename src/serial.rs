@@ -0,0 +1,66 @@
+use mmu::INT_SERIAL;
+
+// M-cycles per bit with the internal 8192 Hz shift clock.
+const CYCLES_PER_BIT: u32 = 128;
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    bits_left: u8,
+    cycles: u32,
+    // every byte shifted out, for test ROMs and anything else that logs over the link port.
+    pub output: Vec<u8>,
+    pub interrupt: u8
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits_left: 0,
+            cycles: 0,
+            output: Vec::new(),
+            interrupt: 0
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            _ => self.sc | 0x7E
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            _ => {
+                self.sc = val & 0x81;
+                if val & 0x80 != 0 {
+                    self.output.push(self.sb);
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        // with the external clock selected nothing happens, since nothing is ever plugged in.
+        if self.sc != 0x81 {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_BIT && self.bits_left > 0 {
+            self.cycles -= CYCLES_PER_BIT;
+            // with no link partner the input line floats high.
+            self.sb = (self.sb << 1) | 1;
+            self.bits_left -= 1;
+            if self.bits_left == 0 {
+                self.sc &= 0x7F;
+                self.interrupt |= INT_SERIAL;
+            }
+        }
+    }
+}
\ No newline at end of file
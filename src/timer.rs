@@ -0,0 +1,221 @@
+use mmu::INT_TIMER;
+
+pub struct Timer {
+    // DIV is the upper byte of this free-running counter, which advances every T-cycle.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA reads 0 for one M-cycle after overflowing; the TMA reload and the interrupt
+    // happen on the cycle after that.
+    overflow: bool,
+    // set for the M-cycle in which TIMA is reloaded, when writes to TIMA are ignored
+    // and writes to TMA go straight through to TIMA.
+    reloading: bool,
+    pub interrupt: u8
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+            interrupt: 0
+        }
+    }
+
+    // Sets the internal counter directly, as the boot ROM would have left it.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            _ => self.tac | 0xF8
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => { // any write resets the whole counter
+                let old = self.signal();
+                self.counter = 0;
+                self.detect_edge(old);
+            },
+            0xFF05 => {
+                if !self.reloading {
+                    // writing during the overflow cycle cancels the pending reload.
+                    self.overflow = false;
+                    self.tima = val;
+                }
+            },
+            0xFF06 => {
+                self.tma = val;
+                if self.reloading {
+                    self.tima = val;
+                }
+            },
+            _ => {
+                let old = self.signal();
+                self.tac = val & 0x07;
+                self.detect_edge(old);
+            }
+        }
+    }
+
+    // TIMA is clocked by the falling edge of (selected counter bit AND timer enable),
+    // which is why resetting DIV or changing TAC can tick it early.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7     // 16384 Hz
+        };
+        self.tac & 0x04 != 0 && (self.counter >> bit) & 1 != 0
+    }
+
+    fn detect_edge(&mut self, old: bool) {
+        if old && !self.signal() {
+            let (tima, overflowed) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            if overflowed {
+                self.overflow = true;
+            }
+        }
+    }
+
+    fn tick_mcycle(&mut self) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.tima = self.tma;
+            self.interrupt |= INT_TIMER;
+            self.reloading = true;
+        }
+        let old = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(old);
+    }
+
+    // Advances the timer by `cycles` M-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.tick_mcycle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TAC 0x05 selects counter bit 3; a counter of 0x0C makes the next M-cycle a falling edge.
+    fn about_to_overflow() -> Timer {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF06, 0x42);
+        timer.set_counter(0x000C);
+        timer
+    }
+
+    #[test]
+    fn div_advances_every_64_mcycles() {
+        let mut timer = Timer::new();
+        timer.tick(63);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+        timer.tick(1);
+        assert_eq!(timer.read_byte(0xFF04), 1);
+        timer.write_byte(0xFF04, 0x99);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+    }
+
+    #[test]
+    fn tac_reads_upper_bits_set() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0xFF);
+        assert_eq!(timer.read_byte(0xFF07), 0xFF);
+        timer.write_byte(0xFF07, 0x00);
+        assert_eq!(timer.read_byte(0xFF07), 0xF8);
+    }
+
+    #[test]
+    fn tima_counts_at_selected_rate() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.tick(4 * 10);
+        assert_eq!(timer.read_byte(0xFF05), 10);
+        timer.write_byte(0xFF07, 0x04);
+        timer.write_byte(0xFF05, 0);
+        timer.set_counter(0);
+        timer.tick(256);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+    }
+
+    #[test]
+    fn div_reset_ticks_tima_on_falling_edge() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.set_counter(0x0008);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+        // with the selected bit low there is no edge.
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+    }
+
+    #[test]
+    fn tac_write_ticks_tima_on_falling_edge() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.set_counter(0x0008);
+        timer.write_byte(0xFF07, 0x00);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+        // switching to a rate whose bit is low is also a falling edge.
+        timer.write_byte(0xFF07, 0x05);
+        timer.write_byte(0xFF07, 0x06);
+        assert_eq!(timer.read_byte(0xFF05), 2);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_mcycle_late() {
+        let mut timer = about_to_overflow();
+        timer.tick(1);
+        assert_eq!(timer.read_byte(0xFF05), 0);
+        assert_eq!(timer.interrupt, 0);
+        timer.tick(1);
+        assert_eq!(timer.read_byte(0xFF05), 0x42);
+        assert_eq!(timer.interrupt, INT_TIMER);
+    }
+
+    #[test]
+    fn tima_write_cancels_pending_reload() {
+        let mut timer = about_to_overflow();
+        timer.tick(1);
+        timer.write_byte(0xFF05, 0x10);
+        timer.tick(1);
+        assert_eq!(timer.read_byte(0xFF05), 0x10);
+        assert_eq!(timer.interrupt, 0);
+    }
+
+    #[test]
+    fn writes_during_reload_cycle() {
+        let mut timer = about_to_overflow();
+        timer.tick(2);
+        timer.write_byte(0xFF05, 0x10);
+        assert_eq!(timer.read_byte(0xFF05), 0x42);
+        timer.write_byte(0xFF06, 0x77);
+        assert_eq!(timer.read_byte(0xFF05), 0x77);
+        timer.tick(1);
+        timer.write_byte(0xFF06, 0x55);
+        assert_eq!(timer.read_byte(0xFF05), 0x77);
+    }
+}
\ No newline at end of file
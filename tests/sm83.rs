@@ -0,0 +1,215 @@
+// Runs the community SM83 single-step tests: one JSON file per opcode (00.json ..
+// ff.json, cb 00.json .. cb ff.json), each holding a list of cases with the CPU and
+// memory state before and after one instruction, plus the bus activity per M-cycle.
+//
+// The files are read from $GBEM_SM83_TESTS (or test_roms/sm83 in the crate root)
+// and the test is skipped when that's missing, unless $GBEM_REQUIRE_ROMS is set,
+// in which case it fails.
+extern crate gb_em;
+extern crate serde_json;
+use gb_em::cpu::CPU;
+use gb_em::bus::Bus;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+fn test_dir() -> Option<PathBuf> {
+    let dir = env::var_os("GBEM_SM83_TESTS").map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms").join("sm83"));
+    if dir.is_dir() {
+        return Some(dir);
+    }
+    skip(&format!("no SM83 test directory at {}", dir.display()));
+    None
+}
+
+// Same as the ROM suites: stderr stays visible under the test harness.
+fn skip(reason: &str) {
+    assert!(env::var_os("GBEM_REQUIRE_ROMS").is_none(), "GBEM_REQUIRE_ROMS is set, but {}", reason);
+    let _ = writeln!(io::stderr(), "skipped: {}", reason);
+}
+
+// One M-cycle on the bus: the address, the data and whether it was a read or a
+// write, or None for a cycle that didn't touch memory.
+type Access = Option<(u16, u8, char)>;
+
+// The whole address space as plain RAM with nothing else attached, which is what
+// the tests assume, logging each M-cycle's bus access.
+struct TestBus {
+    memory: Vec<u8>,
+    cycles: Vec<Access>
+}
+
+impl TestBus {
+    // An access lands in the M-cycle just ticked, unless that cycle already had
+    // one; then it gets a cycle of its own, so it shows up as a mismatch.
+    fn record(&mut self, access: (u16, u8, char)) {
+        match self.cycles.last_mut() {
+            Some(last) if last.is_none() => *last = Some(access),
+            _ => self.cycles.push(Some(access))
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        let val = self.memory[addr as usize];
+        self.record((addr, val, 'r'));
+        val
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        self.memory[addr as usize] = val;
+        self.record((addr, val, 'w'));
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.cycles.push(None);
+        }
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    fn acknowledge_interrupt(&mut self, _bit: u8) {}
+
+    fn stall(&mut self) -> bool {
+        false
+    }
+
+    fn set_halted(&mut self, _halted: bool) {}
+
+    fn joypad_active(&self) -> bool {
+        false
+    }
+
+    fn stop(&mut self) -> bool {
+        false
+    }
+}
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name].as_u64().unwrap_or_else(|| panic!("missing field {}", name)) as u16
+}
+
+fn setup(state: &Value) -> CPU<TestBus> {
+    let mut cpu = CPU::with_bus(TestBus { memory: vec![0; 0x10000], cycles: Vec::new() });
+    cpu.pc = field(state, "pc");
+    cpu.sp = field(state, "sp");
+    let r = &mut cpu.registers;
+    r.set_af((field(state, "a") << 8) | field(state, "f"));
+    r.b = field(state, "b") as u8;
+    r.c = field(state, "c") as u8;
+    r.d = field(state, "d") as u8;
+    r.e = field(state, "e") as u8;
+    r.h = field(state, "h") as u8;
+    r.l = field(state, "l") as u8;
+    cpu.ime = field(state, "ime") != 0;
+    for entry in state["ram"].as_array().unwrap() {
+        let addr = entry[0].as_u64().unwrap() as u16;
+        cpu.mmu.memory[addr as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+    cpu
+}
+
+// Lists every way the CPU's state differs from `state`.
+fn compare(cpu: &CPU<TestBus>, state: &Value) -> Vec<String> {
+    let mut diffs = Vec::new();
+    let af = cpu.registers.af();
+    let actual = [
+        ("pc", cpu.pc), ("sp", cpu.sp), ("a", af >> 8), ("f", af & 0xFF),
+        ("b", cpu.registers.b as u16), ("c", cpu.registers.c as u16),
+        ("d", cpu.registers.d as u16), ("e", cpu.registers.e as u16),
+        ("h", cpu.registers.h as u16), ("l", cpu.registers.l as u16),
+        ("ime", cpu.ime as u16)
+    ];
+    for &(name, value) in actual.iter() {
+        let expected = field(state, name);
+        if value != expected {
+            diffs.push(format!("{}: expected {:04x}, got {:04x}", name, expected, value));
+        }
+    }
+    for entry in state["ram"].as_array().unwrap() {
+        let addr = entry[0].as_u64().unwrap() as u16;
+        let expected = entry[1].as_u64().unwrap() as u8;
+        let value = cpu.mmu.memory[addr as usize];
+        if value != expected {
+            diffs.push(format!("({:04x}): expected {:02x}, got {:02x}", addr, expected, value));
+        }
+    }
+    diffs
+}
+
+// The expected bus activity: each cycle is [address, data, pins], where the pins
+// read "r-m" for a read and "-wm" for a write. Cycles that don't access memory are
+// null, or have neither pin set, and their address and data aren't checked.
+fn expected_cycles(case: &Value) -> Vec<Access> {
+    case["cycles"].as_array().unwrap().iter().map(|cycle| {
+        let pins = cycle[2].as_str().unwrap_or("");
+        let kind = if pins.contains('r') { 'r' } else if pins.contains('w') { 'w' } else { return None };
+        Some((cycle[0].as_u64().unwrap() as u16, cycle[1].as_u64().unwrap() as u8, kind))
+    }).collect()
+}
+
+fn format_cycles(cycles: &[Access]) -> String {
+    let cycles: Vec<String> = cycles.iter().map(|cycle| match *cycle {
+        Some((addr, val, kind)) => format!("{}({:04x})={:02x}", kind, addr, val),
+        None => "-".to_string()
+    }).collect();
+    cycles.join(" ")
+}
+
+// Runs every case in one file; returns the number that failed and what was wrong
+// with the first of them.
+fn run_file(path: &Path) -> (usize, String) {
+    let text = fs::read_to_string(path).unwrap();
+    let cases: Value = serde_json::from_str(&text).unwrap();
+    let mut failed = 0;
+    let mut first = String::new();
+    for case in cases.as_array().unwrap() {
+        let mut cpu = setup(&case["initial"]);
+        let cycles = cpu.step();
+        let mut diffs = compare(&cpu, &case["final"]);
+        let expected = expected_cycles(case);
+        if cycles as usize != expected.len() {
+            diffs.push(format!("cycles: expected {}, got {}", expected.len(), cycles));
+        }
+        if cpu.mmu.cycles != expected {
+            diffs.push(format!("bus: expected {}, got {}", format_cycles(&expected), format_cycles(&cpu.mmu.cycles)));
+        }
+        if !diffs.is_empty() {
+            if failed == 0 {
+                first = format!("{}: {}", case["name"].as_str().unwrap_or("?"), diffs.join(", "));
+            }
+            failed += 1;
+        }
+    }
+    (failed, first)
+}
+
+#[test]
+fn sm83() {
+    let dir = match test_dir() {
+        Some(dir) => dir,
+        None => return
+    };
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    let mut failing = Vec::new();
+    for path in &files {
+        let (failed, first) = run_file(path);
+        if failed > 0 {
+            failing.push(format!("{} ({} failed, first {})", path.file_stem().unwrap().to_string_lossy(), failed, first));
+        }
+    }
+    println!("{}/{} opcodes pass", files.len() - failing.len(), files.len());
+    assert!(failing.is_empty(), "failing opcodes:\n{}", failing.join("\n"));
+}
\ No newline at end of file
@@ -0,0 +1,235 @@
+// Runs Blargg's and Mooneye's test ROMs and prints a pass matrix.
+//
+// The ROMs aren't redistributable, so they're read from $GBEM_TEST_ROMS (or
+// test_roms/ in the crate root) and the tests are skipped when that's missing,
+// unless $GBEM_REQUIRE_ROMS is set, in which case they fail:
+//
+//     test_roms/blargg/cpu_instrs/individual/01-special.gb
+//     test_roms/blargg/instr_timing/instr_timing.gb
+//     test_roms/blargg/mem_timing/individual/01-read_timing.gb
+//     test_roms/mooneye/acceptance/...
+//
+// tests/rom_baseline.txt records the result of every ROM from a previous run; a ROM
+// that passed there and doesn't now fails the test, so accuracy regressions show
+// up. Run with $GBEM_UPDATE_BASELINE set to rewrite it from the current results.
+// The baseline starts out empty, since the ROMs aren't checked in; until a suite
+// has been recorded its results are only printed, with a warning.
+extern crate gb_em;
+use gb_em::cpu::CPU;
+use gb_em::cartridge::Cartridge;
+use gb_em::mbc::RtcClock;
+use gb_em::headless::{self, Condition, Outcome};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+// Mooneye's tests signal success by loading the Fibonacci numbers into B-L before LD B,B,
+// and failure by loading 0x42 everywhere.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const LD_B_B: u8 = 0x40;
+
+const BASELINE_HEADER: &str = "\
+# The result of every test ROM in the last full run, relative to the test ROM
+# directory. tests/test_roms.rs fails if a ROM listed as Pass stops passing.
+# Regenerate with GBEM_UPDATE_BASELINE=1 cargo test --test test_roms.
+";
+
+// Both suites run at once and share the baseline file.
+static BASELINE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Result {
+    Pass,
+    Fail,
+    Timeout,
+    LoadError,
+    // the emulator panicked
+    Crash
+}
+
+fn rom_dir() -> Option<PathBuf> {
+    let dir = env::var_os("GBEM_TEST_ROMS").map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms"));
+    if dir.is_dir() {
+        return Some(dir);
+    }
+    skip(&format!("no test ROM directory at {}", dir.display()));
+    None
+}
+
+// Reports a suite that couldn't run. The test harness captures println!, so this
+// goes straight to stderr to stay visible.
+fn skip(reason: &str) {
+    assert!(env::var_os("GBEM_REQUIRE_ROMS").is_none(), "GBEM_REQUIRE_ROMS is set, but {}", reason);
+    let _ = writeln!(io::stderr(), "skipped: {}", reason);
+}
+
+// Every .gb file under `dir`, sorted so the matrix comes out in a stable order.
+fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return roms
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            roms.extend(find_roms(&path));
+        } else if path.extension().is_some_and(|ext| ext == "gb") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    roms
+}
+
+fn load(path: &Path) -> Option<CPU> {
+    let cartridge = Cartridge::from_file_with_clock(path, RtcClock::Emulated).ok()?;
+    let mut cpu = CPU::new();
+    cpu.load_cartridge(cartridge);
+    Some(cpu)
+}
+
+fn run_blargg(path: &Path) -> Result {
+    let mut cpu = match load(path) {
+        Some(cpu) => cpu,
+        None => return Result::LoadError
+    };
+    // the full cpu_instrs takes close to a minute of emulated time
+    let conditions = [Condition::Serial(b"Passed".to_vec()), Condition::Serial(b"Failed".to_vec())];
+    match headless::run(&mut cpu, 4000, &conditions) {
+        Outcome::Met(0) => Result::Pass,
+        Outcome::Met(_) => Result::Fail,
+        Outcome::FrameLimit => Result::Timeout
+    }
+}
+
+fn run_mooneye(path: &Path) -> Result {
+    let mut cpu = match load(path) {
+        Some(cpu) => cpu,
+        None => return Result::LoadError
+    };
+    match headless::run(&mut cpu, 1200, &[Condition::Opcode(LD_B_B)]) {
+        Outcome::Met(_) => {
+            let r = &cpu.registers;
+            if [r.b, r.c, r.d, r.e, r.h, r.l] == MOONEYE_PASS { Result::Pass } else { Result::Fail }
+        },
+        Outcome::FrameLimit => Result::Timeout
+    }
+}
+
+// Mooneye ROM names end in the models they're meant for, e.g. -dmgABC, -GS or -cgb.
+// Only run the ones written for (or not specific to) the DMG.
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    match stem.rfind('-') {
+        Some(i) => {
+            let models = &stem[i + 1..];
+            let groups = !models.is_empty() && models.chars().all(|c| c.is_ascii_uppercase());
+            models.contains("dmgABC") || (groups && models.contains('G'))
+        },
+        None => true
+    }
+}
+
+fn baseline_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("rom_baseline.txt")
+}
+
+// ROM name to result, from lines of the form "Pass blargg/cpu_instrs/cpu_instrs.gb".
+fn read_baseline() -> HashMap<String, String> {
+    fs::read_to_string(baseline_path()).unwrap_or_default().lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let result = parts.next()?;
+            Some((parts.next()?.trim().to_string(), result.to_string()))
+        })
+        .collect()
+}
+
+// Replaces one suite's entries in the baseline file with `results`.
+fn write_baseline(suite: &str, results: &[(String, Result)]) {
+    let prefix = format!("{}/", suite);
+    let mut entries: Vec<(String, String)> = read_baseline().into_iter()
+        .filter(|(name, _)| !name.starts_with(&prefix))
+        .collect();
+    entries.extend(results.iter().map(|(name, result)| (name.clone(), format!("{:?}", result))));
+    entries.sort();
+    let mut text = BASELINE_HEADER.to_string();
+    for (name, result) in entries {
+        text.push_str(&format!("{:<9} {}\n", result, name));
+    }
+    fs::write(baseline_path(), text).unwrap();
+}
+
+// Runs every ROM on its own thread, prints the matrix and checks it against the baseline.
+fn run_suite(root: &Path, suite: &str, roms: Vec<PathBuf>, run: fn(&Path) -> Result) {
+    if roms.is_empty() {
+        return skip(&format!("no {} ROMs under {}", suite, root.join(suite).display()));
+    }
+    let handles: Vec<_> = roms.into_iter().map(|path| {
+        let rom = path.clone();
+        (path, thread::spawn(move || run(&rom)))
+    }).collect();
+    let results: Vec<(String, Result)> = handles.into_iter().map(|(path, handle)| {
+        let result = handle.join().unwrap_or(Result::Crash);
+        let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        (name, result)
+    }).collect();
+    let passed = results.iter().filter(|&&(_, result)| result == Result::Pass).count();
+    println!("{}: {}/{} passed", suite, passed, results.len());
+    for (name, result) in &results {
+        println!("    {:<9} {}", format!("{:?}", result), name);
+    }
+    let _lock = BASELINE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if env::var_os("GBEM_UPDATE_BASELINE").is_some() {
+        return write_baseline(suite, &results);
+    }
+    let baseline = read_baseline();
+    let unrecorded = results.iter().filter(|(name, _)| !baseline.contains_key(name)).count();
+    if unrecorded == results.len() {
+        // nothing to compare against yet, so there's no regression to report.
+        let _ = writeln!(io::stderr(), "warning: tests/rom_baseline.txt has no {} results; \
+            run with GBEM_UPDATE_BASELINE=1 to record them", suite);
+        return;
+    }
+    if unrecorded > 0 {
+        let _ = writeln!(io::stderr(), "warning: {} {} ROMs aren't in the baseline yet", unrecorded, suite);
+    }
+    let regressions: Vec<&String> = results.iter()
+        .filter(|(name, result)| *result != Result::Pass && baseline.get(name).is_some_and(|r| r == "Pass"))
+        .map(|(name, _)| name)
+        .collect();
+    assert!(regressions.is_empty(), "ROMs in the baseline no longer pass: {:?}", regressions);
+}
+
+#[test]
+fn blargg() {
+    let root = match rom_dir() {
+        Some(root) => root,
+        None => return
+    };
+    let mut roms = Vec::new();
+    for suite in &["cpu_instrs", "instr_timing", "mem_timing"] {
+        roms.extend(find_roms(&root.join("blargg").join(suite)));
+    }
+    run_suite(&root, "blargg", roms, run_blargg);
+}
+
+#[test]
+fn mooneye() {
+    let root = match rom_dir() {
+        Some(root) => root,
+        None => return
+    };
+    let roms = find_roms(&root.join("mooneye").join("acceptance")).into_iter()
+        .filter(|path| runs_on_dmg(path))
+        .collect();
+    run_suite(&root, "mooneye", roms, run_mooneye);
+}
\ No newline at end of file